@@ -0,0 +1,323 @@
+use log::{debug, trace};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::cartridge::image_source::{ImageSource, TestPattern, SENSOR_HEIGHT, SENSOR_WIDTH};
+use crate::memory::Address;
+use crate::utils::as_u16;
+
+// https://gbdev.io/pandocs/Gameboy_Camera.html
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const RAM_BANKS: usize = 16;
+
+// RAM bank values with this bit set map the registers into 0xA000-0xBFFF
+const REGISTER_SELECT: u8 = 0x10;
+const REGISTER_COUNT: usize = 0x36;
+
+const TRIGGER: usize = 0x00;
+const FILTER: usize = 0x01;
+const EXPOSURE_HIGH: usize = 0x02;
+const EXPOSURE_LOW: usize = 0x03;
+const EDGE: usize = 0x04;
+const DITHER_MATRIX: usize = 0x06;
+
+const CAPTURE_BUSY: u8 = 0x01;
+const INVERT_OUTPUT: u8 = 0x08;
+
+// Captured pictures are stored as 16x14 tiles at 0xA100 of RAM bank 0
+const IMAGE_OFFSET: usize = 0x0100;
+
+// Edge enhancement ratios selected by register 4 bits 4-6, in quarters
+const EDGE_RATIO: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+// Exposure value at which the sensor reproduces the source unchanged
+const NEUTRAL_EXPOSURE: i32 = 0x0300;
+
+// Only register 0 can be read back, the rest of the area returns zero
+const UNREADABLE_REGISTER: u8 = 0x00;
+
+pub struct PocketCamera {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    registers: [u8; REGISTER_COUNT],
+    current_rom_bank: u8,
+    current_ram_bank: u8,
+    ram_enabled: bool,
+    capture_cycles: u32,
+    source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+    pub fn new(blob: Vec<u8>) -> PocketCamera {
+        PocketCamera::with_source(blob, Box::new(TestPattern))
+    }
+
+    pub fn with_source(blob: Vec<u8>, source: Box<dyn ImageSource>) -> PocketCamera {
+        PocketCamera {
+            data: blob,
+            ram: vec![0; RAM_BANKS * RAM_BANK_SIZE],
+            registers: [0; REGISTER_COUNT],
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            ram_enabled: false,
+            capture_cycles: 0,
+            source,
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        self.current_ram_bank & REGISTER_SELECT != 0
+    }
+
+    fn rom_offset(&self, address: Address) -> usize {
+        let offset = self.current_rom_bank as usize * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+        offset % self.data.len()
+    }
+
+    fn ram_offset(&self, address: Address) -> usize {
+        let bank = (self.current_ram_bank & 0x0F) as usize;
+        bank * RAM_BANK_SIZE + (address as usize - 0xA000)
+    }
+
+    fn write_register(&mut self, register: usize, data: u8) {
+        if register >= REGISTER_COUNT {
+            return;
+        }
+
+        if register == TRIGGER {
+            // Bits 1-2 are kept, bit 0 starts a capture and stays set until it is done
+            let busy = self.registers[TRIGGER] & CAPTURE_BUSY;
+            self.registers[TRIGGER] = (data & 0x06) | busy;
+
+            if data & CAPTURE_BUSY != 0 && busy == 0 {
+                self.registers[TRIGGER] |= CAPTURE_BUSY;
+                self.capture_cycles = self.capture_duration();
+                debug!("Camera capture started, {} cycles", self.capture_cycles);
+            }
+        } else {
+            self.registers[register] = data;
+        }
+    }
+
+    // Pan Docs: 32446 + (N ? 0 : 512) + 16 * exposure M-cycles
+    fn capture_duration(&self) -> u32 {
+        let exposure = as_u16(self.registers[EXPOSURE_HIGH], self.registers[EXPOSURE_LOW]) as u32;
+        let n_bit = self.registers[FILTER] & 0x80 != 0;
+        let m_cycles = 32446 + if n_bit { 0 } else { 512 } + 16 * exposure;
+        m_cycles * 4
+    }
+
+    fn capture(&mut self) {
+        let frame = self.source.capture();
+        let exposed = self.expose(&frame);
+        let enhanced = self.enhance_edges(&exposed);
+        let invert = self.registers[EDGE] & INVERT_OUTPUT != 0;
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let mut value = enhanced[y * SENSOR_WIDTH + x].clamp(0, 255) as u8;
+                if invert {
+                    value = 255 - value;
+                }
+                let shade = self.dither(x, y, value);
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let offset = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+
+                self.ram[offset] = (self.ram[offset] & !(1 << bit)) | ((shade & 0x01) << bit);
+                self.ram[offset + 1] = (self.ram[offset + 1] & !(1 << bit)) | ((shade >> 1) << bit);
+            }
+        }
+
+        self.registers[TRIGGER] &= !CAPTURE_BUSY;
+        debug!("Camera capture finished");
+    }
+
+    fn expose(&self, frame: &[u8]) -> Vec<i32> {
+        let exposure = as_u16(self.registers[EXPOSURE_HIGH], self.registers[EXPOSURE_LOW]) as i32;
+        frame.iter()
+            .map(|&pixel| pixel as i32 * exposure / NEUTRAL_EXPOSURE)
+            .collect()
+    }
+
+    // Register 1 bit 7 (N) and bits 5-6 (VH) select the filter, register 4 the strength
+    fn enhance_edges(&self, image: &[i32]) -> Vec<i32> {
+        let n_bit = self.registers[FILTER] & 0x80 != 0;
+        let vh_bits = (self.registers[FILTER] >> 5) & 0x03;
+        let ratio = EDGE_RATIO[((self.registers[EDGE] >> 4) & 0x07) as usize];
+
+        let pixel = |x: isize, y: isize| {
+            let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+            image[y * SENSOR_WIDTH + x]
+        };
+
+        let mut output = Vec::with_capacity(image.len());
+        for y in 0..SENSOR_HEIGHT as isize {
+            for x in 0..SENSOR_WIDTH as isize {
+                let centre = pixel(x, y);
+                let edge = match (n_bit, vh_bits) {
+                    // Two dimensional enhancement
+                    (true, 0b10) => 4 * centre - pixel(x - 1, y) - pixel(x + 1, y) - pixel(x, y - 1) - pixel(x, y + 1),
+                    // Horizontal enhancement only
+                    (false, 0b10) | (_, 0b01) => 2 * centre - pixel(x - 1, y) - pixel(x + 1, y),
+                    _ => 0,
+                };
+                output.push(centre + edge * ratio / 4);
+            }
+        }
+        output
+    }
+
+    // Every pixel compares against the three thresholds of its 4x4 matrix cell
+    fn dither(&self, x: usize, y: usize, value: u8) -> u8 {
+        let cell = DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.registers[cell..cell + 3];
+
+        if value < thresholds[0] {
+            3
+        } else if value < thresholds[1] {
+            2
+        } else if value < thresholds[2] {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl Cartridge for PocketCamera {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => self.data[self.rom_offset(address)],
+            0xA000 if self.registers_mapped() => self.registers[TRIGGER],
+            0xA000..=0xBFFF if self.registers_mapped() => UNREADABLE_REGISTER,
+            0xA000..=0xBFFF => self.ram[self.ram_offset(address)],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("Camera write {:#X} at {:#X}", data, address);
+
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.current_rom_bank = data & 0x3F,
+            0x4000..=0x5FFF => self.current_ram_bank = data & 0x1F,
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF if self.registers_mapped() => {
+                self.write_register((address as usize - 0xA000) & 0x7F, data)
+            }
+            // RAM cannot be written while the sensor is filling it
+            0xA000..=0xBFFF if self.ram_enabled && self.capture_cycles == 0 => {
+                let offset = self.ram_offset(address);
+                self.ram[offset] = data;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.capture_cycles > 0 {
+            self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+            if self.capture_cycles == 0 {
+                self.capture();
+            }
+        }
+    }
+
+    fn current_banks(&self) -> Banks {
+        let ram = if self.registers_mapped() { None } else { Some((self.current_ram_bank & 0x0F) as usize) };
+        Banks { rom0: 0, romx: self.current_rom_bank as usize, ram }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.ram.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+
+    struct Uniform(u8);
+
+    impl ImageSource for Uniform {
+        fn capture(&mut self) -> Vec<u8> {
+            vec![self.0; SENSOR_WIDTH * SENSOR_HEIGHT]
+        }
+    }
+
+    fn camera(luminance: u8) -> PocketCamera {
+        let mut camera = PocketCamera::with_source(vec![0; 4 * ROM_BANK_SIZE], Box::new(Uniform(luminance)));
+        camera.write(0x4000, REGISTER_SELECT);
+        camera.write(0xA002, 0x03);
+        camera.write(0xA003, 0x00);
+        for cell in 0..16 {
+            camera.write(0xA006 + cell * 3, 0x40);
+            camera.write(0xA007 + cell * 3, 0x80);
+            camera.write(0xA008 + cell * 3, 0xC0);
+        }
+        camera
+    }
+
+    #[test]
+    fn should_stay_busy_until_capture_is_done() {
+        let mut camera = camera(0xFF);
+
+        camera.write(0xA000, 0x01);
+        assert_eq!(camera.read(0xA000) & CAPTURE_BUSY, CAPTURE_BUSY);
+
+        camera.tick(1000);
+        assert_eq!(camera.read(0xA000) & CAPTURE_BUSY, CAPTURE_BUSY);
+
+        camera.tick(camera.capture_duration());
+        assert_eq!(camera.read(0xA000) & CAPTURE_BUSY, 0);
+    }
+
+    #[test]
+    fn should_dither_captured_image_into_ram() {
+        let mut camera = camera(0x50);
+
+        camera.write(0xA000, 0x01);
+        camera.tick(camera.capture_duration());
+        camera.write(0x4000, 0x00);
+
+        // 0x50 falls between the first two thresholds, shade 2
+        assert_eq!(camera.read(0xA100), 0x00);
+        assert_eq!(camera.read(0xA101), 0xFF);
+        assert_eq!(camera.read(0xAEFF), 0xFF);
+    }
+
+    #[test]
+    fn should_invert_output() {
+        let mut camera = camera(0xFF);
+
+        camera.write(0xA004, INVERT_OUTPUT);
+        camera.write(0xA000, 0x01);
+        camera.tick(camera.capture_duration());
+        camera.write(0x4000, 0x00);
+
+        assert_eq!(camera.read(0xA100), 0xFF);
+        assert_eq!(camera.read(0xA101), 0xFF);
+    }
+
+    #[test]
+    fn should_only_read_trigger_register() {
+        let mut camera = camera(0xFF);
+
+        camera.write(0xA001, 0xAA);
+        assert_eq!(camera.read(0xA001), 0x00);
+        assert_eq!(camera.registers[FILTER], 0xAA);
+    }
+}
@@ -1,251 +1,124 @@
 use std::{
     boxed::Box,
-    convert::From,
     vec::Vec,
-    ops
+    fmt
 };
-use log::{debug, error, info};
+use log::info;
 use super::{
     rom::RomOnly,
     mbc1::Mbc1Cartridge,
     mbc2::Mbc2Cartridge,
     mbc3::Mbc3Cartridge,
-    mbc5::Mbc5Cartridge
+    mbc5::Mbc5Cartridge,
+    mbc6::Mbc6Cartridge,
+    tama5::Tama5Cartridge,
+    huc3::Huc3Cartridge,
+    camera::PocketCamera,
+    wisdom_tree::WisdomTreeCartridge,
+    sachen::SachenCartridge,
+    multicart::MulticartCartridge,
+    mapper::Mapper,
+    rtc::ClockFooter,
+    clock::Clock
 };
-use ops::Range;
-use crate::utils::as_u16;
-use crate::soc::instruction::Instruction;
 use crate::memory::Address;
 
-const KB: usize = 1024;
-const MB: usize = KB * 1024;
-const CARTRIDGE_TYPE_LOCATION: usize = 0x0147;
-
-pub trait Cartridge :
-    ops::Index<Address, Output = u8> +
-    ops::Index<Range<Address>, Output = [u8]> +
-    ops::IndexMut<Address, Output = u8>
-{
-    fn report(&self) {
-        info!("[---------- Cartridge Metadata ----------]");
-        info!("Title...........................{}", self.title());
-        info!("Cartridge type..................{}", self.cartridge_type());
-        info!("CGB flag........................{}", self.cgb_flag());
-        info!("SGB flag........................{}", self.sgb_flag());
-        info!("ROM banks.......................{}", self.rom_banks());
-        info!("RAM size........................{}", self.ram_size());
-        info!("Manufacturer code...............{}", self.manufacturer_code());
-        info!("Destination code................{}", self.destination_code());
-        info!("Old license code................{:#X?}", self.old_license_code());
-        info!("New license code................{}", self.new_license_code());
-        info!("Version number..................{}", self.version_number());
-        info!("Checksum........................{}", self.checksum());
-        info!("Global checksum.................{}", self.global_checksum());
-    }
+pub const CARTRIDGE_TYPE_LOCATION: usize = 0x0147;
+
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// Banks mapped into each window of the cartridge area, counted in the mapper's own bank size
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Banks {
+    // 0x0000-0x3FFF, not always bank 0 on MBC1 and multicarts
+    pub rom0: usize,
+    // 0x4000-0x7FFF
+    pub romx: usize,
+    // 0xA000-0xBFFF, none while RAM is disabled, missing or replaced by registers
+    pub ram: Option<usize>,
+}
 
-    // ---------------- Metadata ---------------- //
+pub trait Cartridge {
+    // ---------------- Bus ---------------- //
 
-    fn title(&self) -> String {
-        String::from_utf8_lossy(&self[0x0134..0x0143])
-            .into_owned()
-    }
+    // Whatever the mapper drives on the data lines, 0xFF where nothing does
+    fn read(&self, address: Address) -> u8;
 
-    fn manufacturer_code(&self) -> String {
-        String::from_utf8_lossy(&self[0x013F..0x0142])
-            .into_owned()
-    }
+    // Writes into the ROM area are commands for the mapper, not data
+    fn write(&mut self, address: Address, data: u8);
 
-    fn cgb_flag(&self) -> bool {
-        match self[0x0143] {
-            0x80 => false,
-            0xC0 => true,
-            _ => false
-        }
-    }
+    // Cycles elapsed since the previous call, for mappers with their own hardware
+    fn tick(&mut self, _cycles: u32) {}
 
-    // fn new_license_code(&self) -> String {
-    //     String::from_utf8_lossy(&self[0x0144..0x0145])
-    //         .into_owned()
-    // }
+    // ---------------- Debugging ---------------- //
 
-    fn sgb_flag(&self) -> bool {
-        match self[0x0146] {
-            0x00 => false,
-            0x03 => true,
-            _ => false
-        }
-    }
+    fn current_banks(&self) -> Banks;
 
-    fn cartridge_type(&self) -> &'static str {
-        match self[0x0147] {
-           0x00 => "ROM ONLY",
-           0x19 => "MBC5",
-           0x01 => "MBC1",
-           0x1A => "MBC5+RAM",
-           0x02 => "MBC1+RAM",
-           0x1B => "MBC5+RAM+BATTERY",
-           0x03 => "MBC1+RAM+BATTERY",
-           0x1C => "MBC5+RUMBLE",
-           0x05 => "MBC2",
-           0x1D => "MBC5+RUMBLE+RAM",
-           0x06 => "MBC2+BATTERY",
-           0x1E => "MBC5+RUMBLE+RAM+BATTERY",
-           0x08 => "ROM+RAM",
-           0x20 => "MBC6",
-           0x09 => "ROM+RAM+BATTERY",
-           0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
-           0x0B => "MMM01",
-           0x0C => "MMM01+RAM",
-           0x0D => "MMM01+RAM+BATTERY",
-           0x0F => "MBC3+TIMER+BATTERY",
-           0x10 => "MBC3+TIMER+RAM+BATTERY",
-           0xFC => "POCKET CAMERA",
-           0x11 => "MBC3",
-           0xFD => "BANDAI TAMA5",
-           0x12 => "MBC3+RAM",
-           0xFE => "HuC3",
-           0x13 => "MBC3+RAM+BATTERY",
-           0xFF => "HuC1+RAM+BATTERY",
-              _ => "Unknown"
-        }
-    }
+    // ---------------- Battery ---------------- //
 
-    fn rom_banks(&self) -> u16 {
-        match self[0x0148] {
-            0x00 => 0,
-            0x01 => 4,
-            0x02 => 8,
-            0x03 => 16,
-            0x04 => 32,
-            0x05 => 64,
-            0x06 => 128,
-            0x07 => 256,
-            0x08 => 512,
-            0x52 => 72,
-            0x53 => 80,
-            0x54 => 96,
-            _ => 0
-        }
+    // Battery backed memory that must survive between sessions, if any
+    fn save_ram(&self) -> Option<&[u8]> {
+        None
     }
 
-    fn ram_size(&self) -> u32 {
-        match self[0x0149] {
-            0x00 => 0,
-            0x01 => 2048,
-            0x02 => 8192,
-            0x03 => 32_768,
-            0x04 => 131_072,
-            0x05 => 65_536,
-            _ => 0
-        }
-    }
+    fn load_ram(&mut self, _data: &[u8]) {}
 
-    fn destination_code(&self) -> &'static str {
-        match self[0x014A] {
-            0x00 => "Japanese",
-            0x01 => "Non-Japanese",
-            _ => "Unknown"
-        }
+    // Clock state, saved after the RAM in the footer other emulators use
+    fn save_rtc(&self) -> Option<ClockFooter> {
+        None
     }
 
-    fn new_license_code(&self) -> &'static str {
-        match (self[0x0144], self[0x0145]) {
-            (0x0, 0x0) => "none",
-            (0x0, 0x1) => "Nintendo R&D1",
-            (0x0, 0x8) => "Capcom",
-            (0x1, 0x3) => "Electronic Arts",
-            (0x1, 0x8) => "Hudson Soft",
-            (0x1, 0x9) => "b-ai",
-            (0x2, 0x0) => "kss",
-            (0x2, 0x2) => "pow",
-            (0x2, 0x4) => "PCM Complete",
-            (0x2, 0x5) => "san-x",
-            (0x2, 0x8) => "Kemco Japan",
-            (0x2, 0x9) => "seta",
-            (0x3, 0x0) => "Viacom",
-            (0x3, 0x1) => "Nintendo",
-            (0x3, 0x2) => "Bandai",
-            (0x3, 0x3) => "Ocean/Acclaim",
-            (0x3, 0x4) => "Konami",
-            (0x3, 0x5) => "Hector",
-            (0x3, 0x7) => "Taito",
-            (0x3, 0x8) => "Hudson",
-            (0x3, 0x9) => "Banpresto",
-            (0x4, 0x1) => "UbiSoft",
-            (0x4, 0x2) => "Atlus",
-            (0x4, 0x4) => "Malibu",
-            (0x4, 0x6) => "angel",
-            (0x4, 0x7) => "Bullet-Proof",
-            (0x4, 0x9) => "irem",
-            (0x5, 0x0) => "Absolute",
-            (0x5, 0x1) => "Acclaim",
-            (0x5, 0x2) => "Activision",
-            (0x5, 0x3) => "American sammy",
-            (0x5, 0x4) => "Konami",
-            (0x5, 0x5) => "Hi tech entertainment",
-            (0x5, 0x6) => "LJN",
-            (0x5, 0x7) => "Matchbox",
-            (0x5, 0x8) => "Mattel",
-            (0x5, 0x9) => "Milton Bradley",
-            (0x6, 0x0) => "Titus",
-            (0x6, 0x1) => "Virgin",
-            (0x6, 0x4) => "LucasArts",
-            (0x6, 0x7) => "Ocean",
-            (0x6, 0x9) => "Electronic Arts",
-            (0x7, 0x0) => "Infogrames",
-            (0x7, 0x1) => "Interplay",
-            (0x7, 0x2) => "Broderbund",
-            (0x7, 0x3) => "sculptured",
-            (0x7, 0x5) => "sci",
-            (0x7, 0x8) => "THQ",
-            (0x7, 0x9) => "Accolade",
-            (0x8, 0x0) => "misawa",
-            (0x8, 0x3) => "lozc",
-            (0x8, 0x6) => "tokuma shoten i*",
-            (0x8, 0x7) => "tsukuda ori*",
-            (0x9, 0x1) => "Chunsoft",
-            (0x9, 0x2) => "Video system",
-            (0x9, 0x3) => "Ocean/Acclaim",
-            (0x9, 0x5) => "Varie",
-            (0x9, 0x6) => "Yonezawa/s'pal",
-            (0x9, 0x7) => "Kaneko",
-            (0x9, 0x9) => "Pack in soft",
-            (0xA, 0x4) => "Konami (Yu-Gi-Oh!)",
-            _ => "Unknown"
-        }
-    }
+    fn load_rtc(&mut self, _footer: &ClockFooter) {}
 
-    fn old_license_code(&self) -> u8 {
-        self[0x014B]
-    }
+    // Time source of the cartridge clock, the host clock unless replaced
+    fn set_clock(&mut self, _clock: Box<dyn Clock>) {}
 
-    fn version_number(&self) -> u8 {
-        self[0x014C]
-    }
+    // ---------------- Metadata ---------------- //
+
+    // The whole ROM image, whatever bank is mapped. Metadata is read from its header
+    fn rom(&self) -> &[u8];
 
-    fn checksum(&self) -> u8 {
-        self[0x014D]
+    // False for the empty slot left behind by an ejected cartridge
+    fn is_inserted(&self) -> bool {
+        true
     }
 
-    fn global_checksum(&self) -> u16 {
-        as_u16(self[0x014E], self[0x014F])
+    fn title(&self) -> String {
+        String::from_utf8_lossy(&self.rom()[0x0134..0x0143])
+            .into_owned()
     }
 }
 
-pub fn decode_cartridge(blob: Vec<u8>) -> Box<dyn Cartridge> {
-
-    info!("Decoding cartridge");
-
-    let cartridge_type = blob
-        .get(CARTRIDGE_TYPE_LOCATION as usize)
-        .expect("Error accessing address to find cartridge type");
+impl fmt::Display for Banks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ROM {:#X}/{:#X}", self.rom0, self.romx)?;
+        match self.ram {
+            Some(bank) => write!(f, " RAM {:#X}", bank),
+            None => write!(f, " RAM off"),
+        }
+    }
+}
 
-    match cartridge_type {
-        0x00 | 0x08 | 0x09 => Box::new(RomOnly::new(blob)),
-        0x01 | 0x02 | 0x03 => Box::new(Mbc1Cartridge::new(blob)),
-        5 | 6 => Box::new(Mbc2Cartridge::new(blob)),
-        0x0F..=0x13 => Box::new(Mbc3Cartridge::new(blob)),
-        0x19..=0x1E => Box::new(Mbc5Cartridge::new(blob)),
-        _ => panic!("Unsupported cartridge type: {}", cartridge_type),
+pub fn decode_cartridge_as(blob: Vec<u8>, mapper: Mapper) -> Box<dyn Cartridge> {
+
+    info!("Using {} mapper", mapper);
+
+    match mapper {
+        Mapper::RomOnly => Box::new(RomOnly::new(blob)),
+        Mapper::Mbc1 => Box::new(Mbc1Cartridge::new(blob)),
+        Mapper::Mbc2 => Box::new(Mbc2Cartridge::new(blob)),
+        Mapper::Mbc3 => Box::new(Mbc3Cartridge::new(blob)),
+        Mapper::Mbc5 => Box::new(Mbc5Cartridge::new(blob)),
+        Mapper::Mbc6 => Box::new(Mbc6Cartridge::new(blob)),
+        Mapper::PocketCamera => Box::new(PocketCamera::new(blob)),
+        Mapper::Tama5 => Box::new(Tama5Cartridge::new(blob)),
+        Mapper::Huc3 => Box::new(Huc3Cartridge::new(blob)),
+        Mapper::WisdomTree => Box::new(WisdomTreeCartridge::new(blob)),
+        Mapper::Sachen => Box::new(SachenCartridge::new(blob)),
+        Mapper::Multicart => Box::new(MulticartCartridge::new(blob)),
     }
-}
\ No newline at end of file
+}
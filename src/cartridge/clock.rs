@@ -0,0 +1,141 @@
+use std::str::FromStr;
+use crate::cartridge::rtc;
+
+// The CPU clock, the only time base an emulated clock can count on
+pub const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+// Time cartridge clocks catch up with, in UNIX seconds
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    // Whether the time runs with the host's, even when shifted
+    fn follows_host(&self) -> bool {
+        false
+    }
+
+    // Cycles emulated since the previous call
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockKind {
+    System,
+    Frozen,
+    Cycles,
+}
+
+// Host time, shifted to fast-forward the game without touching the host
+#[derive(Default)]
+pub struct SystemClock {
+    pub offset: u64,
+}
+
+// Stands still, for tests and replays
+pub struct FrozenClock {
+    pub time: u64,
+}
+
+// Counts emulated cycles, so it slows down, pauses and fast-forwards with the emulator
+pub struct CycleClock {
+    time: u64,
+    cycles: u32,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        rtc::now() + self.offset
+    }
+
+    fn follows_host(&self) -> bool {
+        true
+    }
+}
+
+impl Clock for FrozenClock {
+    fn now(&self) -> u64 {
+        self.time
+    }
+}
+
+impl CycleClock {
+    pub fn new(time: u64) -> CycleClock {
+        CycleClock { time, cycles: 0 }
+    }
+}
+
+impl Clock for CycleClock {
+    fn now(&self) -> u64 {
+        self.time
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        self.time += (self.cycles / CYCLES_PER_SECOND) as u64;
+        self.cycles %= CYCLES_PER_SECOND;
+    }
+}
+
+// Frozen and cycle clocks start from the host time unless told otherwise
+pub fn build(kind: ClockKind, start: Option<u64>, offset: u64) -> Box<dyn Clock> {
+    let start = start.unwrap_or_else(rtc::now) + offset;
+    match kind {
+        ClockKind::System => Box::new(SystemClock { offset }),
+        ClockKind::Frozen => Box::new(FrozenClock { time: start }),
+        ClockKind::Cycles => Box::new(CycleClock::new(start)),
+    }
+}
+
+impl FromStr for ClockKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.to_ascii_lowercase().as_str() {
+            "system" => Ok(ClockKind::System),
+            "frozen" => Ok(ClockKind::Frozen),
+            "cycles" => Ok(ClockKind::Cycles),
+            _ => Err(format!("Unknown clock {}, expected system, frozen or cycles", kind)),
+        }
+    }
+}
+
+// Offsets are given as 90, 45s, 30m, 12h or 3d
+pub fn parse_duration(duration: &str) -> Result<u64, String> {
+    let (value, unit) = match duration.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&duration[..index], unit),
+        _ => (duration, 's'),
+    };
+    let seconds = match unit.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(format!("Unknown unit in {}, expected s, m, h or d", duration)),
+    };
+
+    value.parse::<u64>()
+        .map(|value| value * seconds)
+        .map_err(|e| format!("Invalid duration {}: {}", duration, e))
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn should_count_whole_seconds_of_cycles() {
+        let mut clock = CycleClock::new(100);
+        clock.tick(CYCLES_PER_SECOND - 1);
+        assert_eq!(clock.now(), 100);
+
+        clock.tick(CYCLES_PER_SECOND + 1);
+        assert_eq!(clock.now(), 102);
+    }
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("3D"), Ok(3 * 86400));
+        assert!(parse_duration("3w").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::{error, fmt, fs, io};
+use log::{info, warn};
+use crate::cartridge::cartridge::CARTRIDGE_TYPE_LOCATION;
+use crate::cartridge::mapper::{self, Mapper};
+
+// No-Intro tags every name with its regions in parentheses, e.g. "Tetris (Japan) (En) (Rev 1)"
+const REGIONS: [&str; 24] = [
+    "World", "USA", "Europe", "Japan", "Asia", "Australia", "Brazil", "Canada", "China", "France",
+    "Germany", "Hong Kong", "Italy", "Korea", "Netherlands", "Russia", "Scandinavia", "Spain",
+    "Sweden", "Taiwan", "UK", "Unknown", "Latin America", "Greece",
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DumpStatus {
+    Verified,
+    Good,
+    BadDump,
+}
+
+#[derive(Debug)]
+pub struct DumpEntry {
+    pub name: String,
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: Option<String>,
+    pub status: DumpStatus,
+}
+
+pub struct Identification<'a> {
+    pub entry: &'a DumpEntry,
+    // The ROM holds the dump followed by garbage, usually mirrored banks
+    pub overdump: bool,
+    // Mapper the dump needs when its header declares another one
+    pub suggested_mapper: Option<Mapper>,
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    InvalidEntry(String),
+}
+
+// Logiqx XML DAT, the format No-Intro publishes its Game Boy sets in
+pub struct Database {
+    entries: Vec<DumpEntry>,
+}
+
+impl Database {
+    pub fn load(path: &Path) -> Result<Database, DatabaseError> {
+        let database = Database::parse(&fs::read_to_string(path)?)?;
+        info!("Loaded {} dumps from {}", database.entries.len(), path.display());
+        Ok(database)
+    }
+
+    pub fn parse(xml: &str) -> Result<Database, DatabaseError> {
+        let document = roxmltree::Document::parse(xml)?;
+
+        let entries = document.descendants()
+            .filter(|node| node.has_tag_name("game"))
+            .flat_map(|game| {
+                let name = game.attribute("name").unwrap_or_default();
+                game.children()
+                    .filter(|node| node.has_tag_name("rom"))
+                    .map(move |rom| DumpEntry::from_node(name, rom))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Database { entries })
+    }
+
+    // CRC32 narrows the candidates down, SHA-1 settles collisions when the DAT has it
+    pub fn identify(&self, blob: &[u8]) -> Option<Identification<'_>> {
+        if let Some(entry) = self.find(blob) {
+            return Some(Identification::new(entry, false, blob));
+        }
+
+        let sizes: BTreeSet<usize> = self.entries.iter()
+            .map(|entry| entry.size)
+            .filter(|&size| size < blob.len())
+            .collect();
+
+        sizes.into_iter().rev()
+            .find_map(|size| self.find(&blob[..size]))
+            .map(|entry| Identification::new(entry, true, blob))
+    }
+
+    fn find(&self, blob: &[u8]) -> Option<&DumpEntry> {
+        let crc32 = crc32fast::hash(blob);
+        let mut candidates = self.entries.iter()
+            .filter(|entry| entry.size == blob.len() && entry.crc32 == crc32)
+            .peekable();
+
+        candidates.peek()?;
+        let sha1 = sha1_smol::Sha1::from(blob).digest().to_string();
+        candidates.find(|entry| entry.sha1.as_ref().is_none_or(|expected| expected.eq_ignore_ascii_case(&sha1)))
+    }
+}
+
+impl DumpEntry {
+    fn from_node(game: &str, rom: roxmltree::Node) -> Result<DumpEntry, DatabaseError> {
+        let invalid = || DatabaseError::InvalidEntry(game.to_owned());
+
+        let size = rom.attribute("size").and_then(|size| size.parse().ok()).ok_or_else(invalid)?;
+        let crc32 = rom.attribute("crc").and_then(|crc| u32::from_str_radix(crc, 16).ok()).ok_or_else(invalid)?;
+        let status = match rom.attribute("status") {
+            Some("verified") => DumpStatus::Verified,
+            Some("baddump") => DumpStatus::BadDump,
+            // GoodTools marks bad dumps in the name instead
+            _ if game.contains("[b") => DumpStatus::BadDump,
+            _ => DumpStatus::Good,
+        };
+
+        Ok(DumpEntry {
+            name: game.to_owned(),
+            size,
+            crc32,
+            sha1: rom.attribute("sha1").map(str::to_owned),
+            status,
+        })
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.name.split('(').skip(1).filter_map(|tag| tag.split(')').next())
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.tags().find(|tag| tag.split(", ").all(|region| REGIONS.contains(&region)))
+    }
+
+    pub fn revision(&self) -> Option<&str> {
+        self.tags().find(|tag| tag.starts_with("Rev "))
+    }
+}
+
+impl<'a> Identification<'a> {
+    fn new(entry: &'a DumpEntry, overdump: bool, blob: &[u8]) -> Identification<'a> {
+        let declared = blob.get(CARTRIDGE_TYPE_LOCATION).and_then(|&code| Mapper::from_header(code));
+        let suggested_mapper = mapper::detect(&blob[..entry.size]).filter(|&detected| Some(detected) != declared);
+
+        Identification { entry, overdump, suggested_mapper }
+    }
+
+    pub fn report(&self, title: &str) {
+        info!(
+            "Identified {} as {} (region {}, revision {})",
+            title,
+            self.entry.name,
+            self.entry.region().unwrap_or("unknown"),
+            self.entry.revision().unwrap_or("original"),
+        );
+
+        if self.entry.status == DumpStatus::BadDump {
+            warn!("{} is a known bad dump, expect glitches", self.entry.name);
+        }
+        if self.overdump {
+            warn!("ROM is overdumped, only the first {} bytes belong to {}", self.entry.size, self.entry.name);
+        }
+        if let Some(mapper) = self.suggested_mapper {
+            warn!("The header of {} declares the wrong mapper, it needs {}", self.entry.name, mapper);
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(error: io::Error) -> Self {
+        DatabaseError::Io(error)
+    }
+}
+
+impl From<roxmltree::Error> for DatabaseError {
+    fn from(error: roxmltree::Error) -> Self {
+        DatabaseError::Xml(error)
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Io(e) => write!(f, "Cannot read ROM database: {}", e),
+            DatabaseError::Xml(e) => write!(f, "ROM database is not a valid DAT file: {}", e),
+            DatabaseError::InvalidEntry(game) => write!(f, "ROM database entry {} lacks a size or CRC32", game),
+        }
+    }
+}
+
+impl error::Error for DatabaseError {}
+
+#[cfg(test)]
+mod database_tests {
+    use super::*;
+
+    fn database(rom: &[u8], attributes: &str) -> Database {
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+            <datafile>
+                <header><name>Nintendo - Game Boy</name></header>
+                <game name="Tetris (World) (Rev 1)">
+                    <rom name="Tetris (World) (Rev 1).gb" size="{}" crc="{:08X}" {}/>
+                </game>
+            </datafile>"#,
+            rom.len(),
+            crc32fast::hash(rom),
+            attributes,
+        );
+        Database::parse(&xml).unwrap()
+    }
+
+    #[test]
+    fn should_identify_by_checksum() {
+        let rom = vec![0x42; 0x8000];
+        let sha1 = sha1_smol::Sha1::from(&rom).digest().to_string();
+        let database = database(&rom, &format!(r#"sha1="{}" status="verified""#, sha1.to_uppercase()));
+
+        let dump = database.identify(&rom).unwrap();
+        assert_eq!(dump.entry.status, DumpStatus::Verified);
+        assert_eq!(dump.entry.region(), Some("World"));
+        assert_eq!(dump.entry.revision(), Some("Rev 1"));
+        assert!(!dump.overdump);
+
+        assert!(database.identify(&vec![0x24; 0x8000]).is_none());
+    }
+
+    #[test]
+    fn should_reject_sha1_mismatches() {
+        let rom = vec![0x42; 0x8000];
+        let database = database(&rom, r#"sha1="0000000000000000000000000000000000000000""#);
+        assert!(database.identify(&rom).is_none());
+    }
+
+    #[test]
+    fn should_flag_bad_and_overdumps() {
+        let rom = vec![0x42; 0x8000];
+        let database = database(&rom, r#"status="baddump""#);
+
+        let mut overdump = rom.clone();
+        overdump.extend_from_slice(&rom);
+
+        let dump = database.identify(&overdump).unwrap();
+        assert!(dump.overdump);
+        assert_eq!(dump.entry.status, DumpStatus::BadDump);
+    }
+}
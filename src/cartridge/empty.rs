@@ -0,0 +1,30 @@
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::memory::Address;
+
+// Without a cartridge nothing drives the data lines and the pull-up resistors make every
+// read return 0xFF. The CPU ends up executing RST 38 over and over, as real hardware does
+const CARTRIDGE_AREA_SIZE: usize = 0x8000;
+
+static OPEN_BUS_AREA: [u8; CARTRIDGE_AREA_SIZE] = [0xFF; CARTRIDGE_AREA_SIZE];
+
+pub struct EmptySlot;
+
+impl Cartridge for EmptySlot {
+    fn read(&self, _address: Address) -> u8 {
+        0xFF
+    }
+
+    fn write(&mut self, _address: Address, _data: u8) {}
+
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 0, romx: 0, ram: None }
+    }
+
+    fn is_inserted(&self) -> bool {
+        false
+    }
+
+    fn rom(&self) -> &[u8] {
+        &OPEN_BUS_AREA
+    }
+}
@@ -0,0 +1,633 @@
+use std::{error, fmt};
+use std::str::FromStr;
+use log::{info, warn};
+use crate::cartridge::cartridge::NINTENDO_LOGO;
+use crate::cartridge::mapper::Mapper;
+use crate::utils::as_u16;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+pub const HEADER_END: usize = 0x0150;
+
+pub const LOGO: usize = 0x0104;
+pub const TITLE: usize = 0x0134;
+const MANUFACTURER: usize = 0x013F;
+pub const CGB_FLAG: usize = 0x0143;
+pub const NEW_LICENSEE: usize = 0x0144;
+pub const SGB_FLAG: usize = 0x0146;
+pub const CARTRIDGE_TYPE: usize = 0x0147;
+pub const ROM_SIZE: usize = 0x0148;
+pub const RAM_SIZE: usize = 0x0149;
+const DESTINATION: usize = 0x014A;
+pub const OLD_LICENSEE: usize = 0x014B;
+const VERSION: usize = 0x014C;
+pub const HEADER_CHECKSUM: usize = 0x014D;
+pub const GLOBAL_CHECKSUM: usize = 0x014E;
+
+// Old licensee value telling that the new licensee code must be used instead
+pub const USE_NEW_LICENSEE: u8 = 0x33;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CgbSupport {
+    None,
+    Compatible,
+    Only,
+}
+
+impl FromStr for CgbSupport {
+    type Err = String;
+
+    fn from_str(support: &str) -> Result<Self, Self::Err> {
+        match support.to_ascii_lowercase().as_str() {
+            "none" => Ok(CgbSupport::None),
+            "compatible" => Ok(CgbSupport::Compatible),
+            "only" => Ok(CgbSupport::Only),
+            _ => Err(format!("Unknown CGB support {}, expected none, compatible or only", support)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub japanese: bool,
+    pub old_licensee_code: u8,
+    pub new_licensee_code: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum HeaderWarning {
+    LogoMismatch,
+    HeaderChecksumMismatch { declared: u8, computed: u8 },
+    GlobalChecksumMismatch { declared: u16, computed: u16 },
+    RomSizeMismatch { declared: usize, actual: usize },
+    UnknownCartridgeType(u8),
+    UnsupportedCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+}
+
+#[derive(Debug)]
+pub enum HeaderError {
+    Truncated(usize),
+}
+
+impl CartridgeHeader {
+    pub fn parse(blob: &[u8]) -> Result<CartridgeHeader, HeaderError> {
+        if blob.len() < HEADER_END {
+            return Err(HeaderError::Truncated(blob.len()));
+        }
+
+        let cgb = match blob[CGB_FLAG] {
+            0x80 => CgbSupport::Compatible,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+
+        // Colour era titles shrink to 11 characters to make room for a manufacturer code.
+        // Some early colour games still use the full 15 characters, without a code
+        let manufacturer = &blob[MANUFACTURER..CGB_FLAG];
+        let has_manufacturer = cgb != CgbSupport::None
+            && manufacturer.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+
+        let (title, manufacturer_code) = if has_manufacturer {
+            (text(&blob[TITLE..MANUFACTURER]), Some(text(manufacturer)))
+        } else if cgb != CgbSupport::None {
+            (text(&blob[TITLE..CGB_FLAG]), None)
+        } else {
+            (text(&blob[TITLE..NEW_LICENSEE]), None)
+        };
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: blob[SGB_FLAG] == 0x03,
+            cartridge_type: blob[CARTRIDGE_TYPE],
+            rom_size_code: blob[ROM_SIZE],
+            ram_size_code: blob[RAM_SIZE],
+            japanese: blob[DESTINATION] == 0x00,
+            old_licensee_code: blob[OLD_LICENSEE],
+            new_licensee_code: String::from_utf8_lossy(&blob[NEW_LICENSEE..SGB_FLAG]).into_owned(),
+            version: blob[VERSION],
+            header_checksum: blob[HEADER_CHECKSUM],
+            global_checksum: as_u16(blob[GLOBAL_CHECKSUM], blob[GLOBAL_CHECKSUM + 1]),
+        })
+    }
+
+    pub fn rom_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some(0x8000 << self.rom_size_code),
+            0x52 => Some(72 * ROM_BANK_SIZE),
+            0x53 => Some(80 * ROM_BANK_SIZE),
+            0x54 => Some(96 * ROM_BANK_SIZE),
+            _ => None,
+        }
+    }
+
+    pub fn rom_banks(&self) -> Option<usize> {
+        self.rom_size().map(|size| size / ROM_BANK_SIZE)
+    }
+
+    pub fn ram_size(&self) -> Option<usize> {
+        match self.ram_size_code {
+            0x00 => Some(0),
+            0x01 => Some(2048),
+            0x02 => Some(8192),
+            0x03 => Some(32_768),
+            0x04 => Some(131_072),
+            0x05 => Some(65_536),
+            _ => None,
+        }
+    }
+
+    pub fn licensee(&self) -> &'static str {
+        if self.old_licensee_code == USE_NEW_LICENSEE {
+            new_licensee_name(&self.new_licensee_code)
+        } else {
+            old_licensee_name(self.old_licensee_code)
+        }
+    }
+
+    pub fn destination(&self) -> &'static str {
+        if self.japanese { "Japanese" } else { "Non-Japanese" }
+    }
+
+    pub fn validate(&self, blob: &[u8]) -> Vec<HeaderWarning> {
+        let mut warnings = Vec::new();
+
+        if blob[LOGO..LOGO + NINTENDO_LOGO.len()] != NINTENDO_LOGO[..] {
+            warnings.push(HeaderWarning::LogoMismatch);
+        }
+
+        let computed = header_checksum(blob);
+        if computed != self.header_checksum {
+            warnings.push(HeaderWarning::HeaderChecksumMismatch { declared: self.header_checksum, computed });
+        }
+
+        let computed = global_checksum(blob);
+        if computed != self.global_checksum {
+            warnings.push(HeaderWarning::GlobalChecksumMismatch { declared: self.global_checksum, computed });
+        }
+
+        match self.rom_size() {
+            Some(declared) if declared != blob.len() => {
+                warnings.push(HeaderWarning::RomSizeMismatch { declared, actual: blob.len() })
+            }
+            Some(_) => {}
+            None => warnings.push(HeaderWarning::UnknownRomSize(self.rom_size_code)),
+        }
+
+        if self.ram_size().is_none() {
+            warnings.push(HeaderWarning::UnknownRamSize(self.ram_size_code));
+        }
+
+        if cartridge_type_name(self.cartridge_type).is_none() {
+            warnings.push(HeaderWarning::UnknownCartridgeType(self.cartridge_type));
+        } else if Mapper::from_header(self.cartridge_type).is_none() {
+            warnings.push(HeaderWarning::UnsupportedCartridgeType(self.cartridge_type));
+        }
+
+        warnings
+    }
+
+    pub fn report(&self) {
+        info!("[---------- Cartridge Metadata ----------]");
+        info!("Title...........................{}", self.title);
+        info!("Manufacturer code...............{}", self.manufacturer_code.as_deref().unwrap_or("none"));
+        info!(
+            "Cartridge type..................{:#04X} ({})",
+            self.cartridge_type,
+            cartridge_type_name(self.cartridge_type).unwrap_or("unknown")
+        );
+        info!("CGB support.....................{:?}", self.cgb);
+        info!("SGB flag........................{}", self.sgb);
+        info!("ROM banks.......................{}", self.rom_banks().unwrap_or(0));
+        info!("RAM size........................{}", self.ram_size().unwrap_or(0));
+        info!("Destination code................{}", self.destination());
+        info!("Licensee........................{}", self.licensee());
+        info!("Version number..................{}", self.version);
+        info!("Checksum........................{:#04X}", self.header_checksum);
+        info!("Global checksum.................{:#06X}", self.global_checksum);
+    }
+
+    pub fn report_warnings(&self, blob: &[u8]) {
+        for warning in self.validate(blob) {
+            warn!("Header: {}", warning);
+        }
+    }
+}
+
+// x = x - byte - 1 over 0x0134-0x014C, as verified by the boot ROM
+pub fn header_checksum(blob: &[u8]) -> u8 {
+    blob[TITLE..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+// Sum of every byte but the global checksum itself
+pub fn global_checksum(blob: &[u8]) -> u16 {
+    blob.iter()
+        .enumerate()
+        .filter(|(address, _)| *address != GLOBAL_CHECKSUM && *address != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16))
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim_end()
+        .to_owned()
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderWarning::LogoMismatch => write!(f, "Nintendo logo does not match"),
+            HeaderWarning::HeaderChecksumMismatch { declared, computed } => {
+                write!(f, "header checksum is {:#04X} but the header sums to {:#04X}", declared, computed)
+            }
+            HeaderWarning::GlobalChecksumMismatch { declared, computed } => {
+                write!(f, "global checksum is {:#06X} but the ROM sums to {:#06X}", declared, computed)
+            }
+            HeaderWarning::RomSizeMismatch { declared, actual } => {
+                write!(f, "header declares {} bytes of ROM but the file has {}", declared, actual)
+            }
+            HeaderWarning::UnknownCartridgeType(code) => write!(f, "unknown cartridge type {:#04X}", code),
+            HeaderWarning::UnsupportedCartridgeType(code) => write!(
+                f,
+                "cartridge type {:#04X} ({}) has no mapper yet",
+                code,
+                cartridge_type_name(*code).unwrap_or("unknown")
+            ),
+            HeaderWarning::UnknownRomSize(code) => write!(f, "unknown ROM size {:#04X}", code),
+            HeaderWarning::UnknownRamSize(code) => write!(f, "unknown RAM size {:#04X}", code),
+        }
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::Truncated(length) => {
+                write!(f, "ROM is {} bytes long, too short to hold a cartridge header", length)
+            }
+        }
+    }
+}
+
+impl error::Error for HeaderError {}
+
+// Every type the header can declare, whether a mapper exists for it or not
+fn cartridge_type_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn new_licensee_name(code: &str) -> &'static str {
+    match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean Software/Acclaim Entertainment",
+        "34" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "38" => "Hudson Soft",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "54" => "Konami",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games Ltd.",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "93" => "Ocean Software/Acclaim Entertainment",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => "Unknown",
+    }
+}
+
+fn old_licensee_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 => "Atlus",
+        0x44 => "Malibu Interactive",
+        0x46 => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4A => "Virgin Games Ltd.",
+        0x4D => "Malibu Interactive",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x61 => "Virgin Games Ltd.",
+        0x67 => "Ocean Software",
+        0x69 => "Electronic Arts",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay Entertainment",
+        0x72 => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC",
+        0x86 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9D => "Banpresto",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA2 => "Bandai",
+        0xA4 => "Konami",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAA => "Broderbund",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB0 => "Acclaim Entertainment",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB2 => "Bandai",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 => "Taito",
+        0xC2 => "Kemco",
+        0xC3 => "Square",
+        0xC4 => "Tokuma Shoten",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xCE => "Pony Canyon",
+        0xCF => "Angel",
+        0xD0 => "Taito",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD6 => "Naxat Soft",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDB => "LJN",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE0 => "Jaleco",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEB => "Atlus",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        0xFF => "LJN",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    fn rom(title: &[u8]) -> Vec<u8> {
+        let mut blob = vec![0; 0x8000];
+        blob[LOGO..LOGO + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        blob[TITLE..TITLE + title.len()].copy_from_slice(title);
+        blob[HEADER_CHECKSUM] = header_checksum(&blob);
+        let (high, low) = crate::utils::hilo(global_checksum(&blob));
+        blob[GLOBAL_CHECKSUM] = high;
+        blob[GLOBAL_CHECKSUM + 1] = low;
+        blob
+    }
+
+    #[test]
+    fn should_reject_truncated_rom() {
+        assert!(CartridgeHeader::parse(&[0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn should_split_cgb_title_and_manufacturer() {
+        let header = CartridgeHeader::parse(&rom(b"POKEMON_SLVAAXE\xC0")).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Only);
+
+        let header = CartridgeHeader::parse(&rom(b"TETRIS")).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn should_validate_clean_rom() {
+        let blob = rom(b"TETRIS");
+        let header = CartridgeHeader::parse(&blob).unwrap();
+        assert_eq!(header.validate(&blob), vec![]);
+    }
+
+    #[test]
+    fn should_report_each_mismatch() {
+        let mut blob = rom(b"TETRIS");
+        blob[LOGO] = 0x00;
+        blob[ROM_SIZE] = 0x01;
+        blob.truncate(0x6000);
+        let header = CartridgeHeader::parse(&blob).unwrap();
+
+        let warnings = header.validate(&blob);
+        assert!(warnings.contains(&HeaderWarning::LogoMismatch));
+        assert!(warnings.iter().any(|w| matches!(w, HeaderWarning::HeaderChecksumMismatch { .. })));
+        assert!(warnings.iter().any(|w| matches!(w, HeaderWarning::GlobalChecksumMismatch { .. })));
+        assert!(warnings.contains(&HeaderWarning::RomSizeMismatch { declared: 0x10000, actual: 0x6000 }));
+    }
+
+    #[test]
+    fn should_tell_unsupported_from_unknown_cartridge_types() {
+        let mut blob = rom(b"TETRIS");
+        for (cartridge_type, warning) in vec![
+            (0x22, Some(HeaderWarning::UnsupportedCartridgeType(0x22))),
+            (0x0B, Some(HeaderWarning::UnsupportedCartridgeType(0x0B))),
+            (0xFF, Some(HeaderWarning::UnsupportedCartridgeType(0xFF))),
+            (0x42, Some(HeaderWarning::UnknownCartridgeType(0x42))),
+            (0x1B, None),
+        ] {
+            blob[CARTRIDGE_TYPE] = cartridge_type;
+            let warnings = CartridgeHeader::parse(&blob).unwrap().validate(&blob);
+            let type_warnings: Vec<_> = warnings
+                .into_iter()
+                .filter(|w| matches!(w, HeaderWarning::UnknownCartridgeType(_) | HeaderWarning::UnsupportedCartridgeType(_)))
+                .collect();
+            assert_eq!(type_warnings, warning.into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn should_resolve_licensee() {
+        let mut blob = rom(b"TETRIS");
+        blob[OLD_LICENSEE] = 0x01;
+        assert_eq!(CartridgeHeader::parse(&blob).unwrap().licensee(), "Nintendo");
+
+        blob[OLD_LICENSEE] = USE_NEW_LICENSEE;
+        blob[NEW_LICENSEE..SGB_FLAG].copy_from_slice(b"A4");
+        assert_eq!(CartridgeHeader::parse(&blob).unwrap().licensee(), "Konami (Yu-Gi-Oh!)");
+    }
+}
@@ -0,0 +1,353 @@
+use std::convert::TryInto;
+use log::{debug, trace, warn};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::cartridge::clock::{Clock, SystemClock};
+use crate::cartridge::header::CartridgeHeader;
+use crate::cartridge::rtc::{self, ClockFooter};
+use crate::memory::Address;
+
+// Hudson's HuC3 maps RAM, a clock or an infrared port at 0xA000-0xBFFF depending on the
+// mode written into 0x0000-0x1FFF. The clock is driven through nibble sized commands
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+const MODE_RAM_READ: u8 = 0x00;
+const MODE_RAM: u8 = 0x0A;
+const MODE_RTC_COMMAND: u8 = 0x0B;
+const MODE_RTC_RESULT: u8 = 0x0C;
+const MODE_RTC_READY: u8 = 0x0D;
+const MODE_INFRARED: u8 = 0x0E;
+
+// Commands in the upper nibble of a 0x0B mode write, the lower nibble is their argument
+const READ_NEXT: u8 = 0x1;
+const WRITE_NEXT: u8 = 0x3;
+const ADDRESS_LOW: u8 = 0x4;
+const ADDRESS_HIGH: u8 = 0x5;
+const EXTENDED: u8 = 0x6;
+
+// Extended command arguments
+const CLOCK_TO_MEMORY: u8 = 0x0;
+const MEMORY_TO_CLOCK: u8 = 0x1;
+const STATUS: u8 = 0x2;
+
+// The clock counts minutes within the day and days, 12 bits each, stored as nibbles at the
+// start of the clock memory
+const MINUTES_PER_DAY: u64 = 1440;
+const DAYS: u64 = 4096;
+
+// SameBoy's clock footer: a 64 bit UNIX timestamp, the minute and day counters, the alarm
+// minutes and days, then the alarm enable byte. The alarm is not emulated and saved off
+pub const FOOTER_SIZE: usize = 17;
+
+const READY: u8 = 0x01;
+const NO_LIGHT: u8 = 0xC0;
+
+pub struct Huc3Cartridge {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    mode: u8,
+    current_rom_bank: usize,
+    current_ram_bank: usize,
+    clock: Huc3Clock,
+    memory: [u8; 256],
+    address: u8,
+    result: u8,
+}
+
+impl Huc3Cartridge {
+    pub fn new(data: Vec<u8>) -> Huc3Cartridge {
+        let ram_size = CartridgeHeader::parse(&data).ok().and_then(|header| header.ram_size()).unwrap_or(0);
+
+        Huc3Cartridge {
+            data,
+            ram: vec![0; ram_size],
+            mode: MODE_RAM_READ,
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            clock: Huc3Clock::new(Box::new(SystemClock::default())),
+            memory: [0; 256],
+            address: 0,
+            result: 0,
+        }
+    }
+
+    fn ram_offset(&self, address: Address) -> Option<usize> {
+        let offset = self.current_ram_bank * RAM_BANK_SIZE + (address as usize - 0xA000);
+        if offset < self.ram.len() { Some(offset) } else { None }
+    }
+
+    fn execute(&mut self, data: u8) {
+        let (command, argument) = (data >> 4 & 0x07, data & 0x0F);
+
+        match command {
+            READ_NEXT => {
+                self.result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            WRITE_NEXT => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            ADDRESS_LOW => self.address = (self.address & 0xF0) | argument,
+            ADDRESS_HIGH => self.address = (self.address & 0x0F) | (argument << 4),
+            EXTENDED => match argument {
+                CLOCK_TO_MEMORY => {
+                    let (minutes, days) = self.clock.read();
+                    write_nibbles(&mut self.memory[0..3], minutes);
+                    write_nibbles(&mut self.memory[3..6], days);
+                }
+                MEMORY_TO_CLOCK => self.clock.set(read_nibbles(&self.memory[0..3]), read_nibbles(&self.memory[3..6])),
+                STATUS => self.result = 0x01,
+                _ => debug!("Unknown HuC3 extended command {:#X}", argument),
+            },
+            _ => debug!("Unknown HuC3 command {:#X}", command),
+        }
+    }
+}
+
+fn write_nibbles(nibbles: &mut [u8], value: u16) {
+    for (index, nibble) in nibbles.iter_mut().enumerate() {
+        *nibble = (value >> (4 * index)) as u8 & 0x0F;
+    }
+}
+
+fn read_nibbles(nibbles: &[u8]) -> u16 {
+    nibbles.iter().enumerate().fold(0, |value, (index, &nibble)| value | ((nibble as u16 & 0x0F) << (4 * index)))
+}
+
+impl Cartridge for Huc3Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.current_rom_bank * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+                self.data[offset % self.data.len()]
+            }
+            0xA000..=0xBFFF => match self.mode {
+                MODE_RAM_READ | MODE_RAM => self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset]),
+                MODE_RTC_RESULT => self.result,
+                MODE_RTC_READY => READY,
+                MODE_INFRARED => NO_LIGHT,
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("HuC3 write {:#X} at {:#X}", data, address);
+
+        match address {
+            0x0000..=0x1FFF => self.mode = data & 0x0F,
+            0x2000..=0x3FFF => self.current_rom_bank = (data & 0x7F) as usize,
+            0x4000..=0x5FFF => self.current_ram_bank = (data & 0x03) as usize,
+            0xA000..=0xBFFF => match self.mode {
+                MODE_RAM => {
+                    if let Some(offset) = self.ram_offset(address) {
+                        self.ram[offset] = data;
+                    }
+                }
+                MODE_RTC_COMMAND => self.execute(data),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.source.tick(cycles);
+    }
+
+    fn current_banks(&self) -> Banks {
+        let ram = match self.mode {
+            MODE_RAM_READ | MODE_RAM if !self.ram.is_empty() => Some(self.current_ram_bank),
+            _ => None,
+        };
+        Banks { rom0: 0, romx: self.current_rom_bank, ram }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.ram.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn save_rtc(&self) -> Option<ClockFooter> {
+        Some(ClockFooter::Huc3(self.clock.footer()))
+    }
+
+    fn load_rtc(&mut self, footer: &ClockFooter) {
+        match footer.huc3() {
+            Some(footer) => self.clock.load_footer(&footer),
+            None => warn!("Ignoring a clock footer saved by another mapper"),
+        }
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock.set_source(clock);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// Seconds are not visible to the game but are kept so that no time is lost between saves
+struct Huc3Clock {
+    seconds: u64,
+    last_update: u64,
+    source: Box<dyn Clock>,
+}
+
+impl Huc3Clock {
+    fn new(source: Box<dyn Clock>) -> Huc3Clock {
+        Huc3Clock { seconds: 0, last_update: source.now(), source }
+    }
+
+    fn set_source(&mut self, source: Box<dyn Clock>) {
+        self.update();
+        self.source = source;
+        self.last_update = self.source.now();
+    }
+
+    fn update(&mut self) {
+        self.seconds = self.current();
+        self.last_update = self.source.now();
+    }
+
+    // Seconds as of now, without moving the last update
+    fn current(&self) -> u64 {
+        (self.seconds + self.source.now().saturating_sub(self.last_update)) % (DAYS * MINUTES_PER_DAY * 60)
+    }
+
+    fn read(&mut self) -> (u16, u16) {
+        self.update();
+        let minutes = self.seconds / 60;
+        ((minutes % MINUTES_PER_DAY) as u16, (minutes / MINUTES_PER_DAY) as u16)
+    }
+
+    fn set(&mut self, minutes: u16, days: u16) {
+        self.update();
+        self.seconds = (days as u64 * MINUTES_PER_DAY + minutes as u64 % MINUTES_PER_DAY) * 60;
+    }
+
+    // Seconds have no place in the footer, they go into the timestamp instead
+    fn footer(&self) -> Huc3Footer {
+        Huc3Footer::from_seconds(self.current(), rtc::stamp(self.source.as_ref()))
+    }
+
+    fn load_footer(&mut self, footer: &Huc3Footer) {
+        let elapsed = rtc::elapsed_since(self.source.as_ref(), footer.timestamp);
+        self.seconds = (footer.seconds() + elapsed) % (DAYS * MINUTES_PER_DAY * 60);
+        self.last_update = self.source.now();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Huc3Footer {
+    pub minutes: u16,
+    pub days: u16,
+    pub timestamp: u64,
+}
+
+impl Huc3Footer {
+    pub fn from_seconds(seconds: u64, timestamp: u64) -> Huc3Footer {
+        Huc3Footer {
+            minutes: (seconds / 60 % MINUTES_PER_DAY) as u16,
+            days: (seconds / 60 / MINUTES_PER_DAY % DAYS) as u16,
+            timestamp: timestamp.saturating_sub(seconds % 60),
+        }
+    }
+
+    pub fn seconds(&self) -> u64 {
+        (self.days as u64 % DAYS * MINUTES_PER_DAY + self.minutes as u64 % MINUTES_PER_DAY) * 60
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Huc3Footer> {
+        if bytes.len() != FOOTER_SIZE {
+            return None;
+        }
+        let half = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        Some(Huc3Footer {
+            minutes: half(8),
+            days: half(10),
+            timestamp: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.timestamp.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.minutes.to_le_bytes());
+        bytes.extend_from_slice(&self.days.to_le_bytes());
+        bytes.extend_from_slice(&[0; 5]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod huc3_tests {
+    use super::*;
+    use crate::cartridge::clock::FrozenClock;
+
+    fn command(cartridge: &mut Huc3Cartridge, command: u8, argument: u8) {
+        cartridge.write(0xA000, (command << 4) | argument);
+    }
+
+    #[test]
+    fn should_set_and_read_clock_through_commands() {
+        let mut cartridge = Huc3Cartridge::new(vec![0; 0x8000]);
+        cartridge.set_clock(Box::new(FrozenClock { time: 1_600_000_000 }));
+        cartridge.write(0x0000, MODE_RTC_COMMAND);
+
+        // 0x123 minutes and 0x045 days
+        command(&mut cartridge, ADDRESS_LOW, 0);
+        command(&mut cartridge, ADDRESS_HIGH, 0);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x4, 0x0] {
+            command(&mut cartridge, WRITE_NEXT, nibble);
+        }
+        command(&mut cartridge, EXTENDED, MEMORY_TO_CLOCK);
+        assert_eq!(cartridge.clock.read(), (0x123, 0x045));
+
+        cartridge.memory = [0; 256];
+        command(&mut cartridge, EXTENDED, CLOCK_TO_MEMORY);
+        command(&mut cartridge, ADDRESS_LOW, 1);
+        command(&mut cartridge, READ_NEXT, 0);
+
+        cartridge.write(0x0000, MODE_RTC_RESULT);
+        assert_eq!(cartridge.read(0xA000), 0x2);
+    }
+
+    #[test]
+    fn should_keep_clock_in_footer() {
+        let mut clock = Huc3Clock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        clock.set(600, 1000);
+        let footer = clock.footer();
+        assert_eq!((footer.minutes, footer.days), (600, 1000));
+        assert_eq!(footer.timestamp, 1_600_000_000);
+
+        let bytes = footer.to_bytes();
+        assert_eq!(bytes.len(), FOOTER_SIZE);
+        assert_eq!(&bytes[8..12], &[0x58, 0x02, 0xE8, 0x03]);
+        assert_eq!(Huc3Footer::parse(&bytes), Some(footer));
+
+        let mut restored = Huc3Clock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        restored.load_footer(&footer);
+        assert_eq!(restored.read(), (600, 1000));
+    }
+
+    #[test]
+    fn should_keep_seconds_in_timestamp() {
+        let mut clock = Huc3Clock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        clock.seconds = 90;
+        let footer = clock.footer();
+        assert_eq!(footer.minutes, 1);
+        assert_eq!(footer.timestamp, 1_600_000_000 - 30);
+
+        let mut restored = Huc3Clock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        restored.load_footer(&footer);
+        assert_eq!(restored.seconds, 90);
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use log::{debug, error};
+
+// Area of the sensor that ends up in the captured picture
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+// Anything able to feed the Pocket Camera sensor. Frames are SENSOR_WIDTH * SENSOR_HEIGHT
+// luminance values in row order, 0 being black and 255 white
+pub trait ImageSource {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+pub fn open(path: &Path) -> io::Result<Box<dyn ImageSource>> {
+    if path.is_dir() {
+        Ok(Box::new(FrameSequence::new(path)?))
+    } else {
+        Ok(Box::new(StillImage::new(path)?))
+    }
+}
+
+// Horizontal gradient, used when no image has been provided
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        (0..SENSOR_WIDTH * SENSOR_HEIGHT)
+            .map(|pixel| ((pixel % SENSOR_WIDTH) * 255 / (SENSOR_WIDTH - 1)) as u8)
+            .collect()
+    }
+}
+
+// The same picture on every capture
+pub struct StillImage {
+    frame: Vec<u8>,
+}
+
+impl StillImage {
+    pub fn new(path: &Path) -> io::Result<StillImage> {
+        Ok(StillImage { frame: decode_png(path)? })
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.frame.clone()
+    }
+}
+
+// Every PNG of a directory in name order, one per capture, looping at the end
+pub struct FrameSequence {
+    frames: Vec<PathBuf>,
+    next: usize,
+    last: Vec<u8>,
+}
+
+impl FrameSequence {
+    pub fn new(directory: &Path) -> io::Result<FrameSequence> {
+        let mut frames: Vec<PathBuf> = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+            .collect();
+        frames.sort();
+
+        if frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No PNG frames found in directory"));
+        }
+
+        debug!("Loaded {} camera frames from {}", frames.len(), directory.display());
+        Ok(FrameSequence { frames, next: 0, last: TestPattern.capture() })
+    }
+}
+
+impl ImageSource for FrameSequence {
+    fn capture(&mut self) -> Vec<u8> {
+        let path = &self.frames[self.next];
+        self.next = (self.next + 1) % self.frames.len();
+
+        // Frames are read on demand, so a broken one just repeats the previous picture
+        match decode_png(path) {
+            Ok(frame) => self.last = frame,
+            Err(e) => error!("Cannot read camera frame {}: {}", path.display(), e),
+        }
+        self.last.clone()
+    }
+}
+
+// Decodes a PNG into sensor sized luminance values, scaling with nearest neighbour
+fn decode_png(path: &Path) -> io::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Indexed PNG was not expanded"))
+        }
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut frame = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+
+    for y in 0..SENSOR_HEIGHT {
+        for x in 0..SENSOR_WIDTH {
+            let offset = ((y * height / SENSOR_HEIGHT) * width + x * width / SENSOR_WIDTH) * channels;
+            let pixel = &buffer[offset..offset + channels];
+
+            let luminance = if channels < 3 {
+                pixel[0]
+            } else {
+                ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as u8
+            };
+            frame.push(luminance);
+        }
+    }
+
+    Ok(frame)
+}
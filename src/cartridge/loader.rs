@@ -0,0 +1,131 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use log::info;
+
+pub const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+pub const ARCHIVE_EXTENSIONS: [&str; 2] = ["zip", "gz"];
+
+pub struct RomFile {
+    pub blob: Vec<u8>,
+    // Where the ROM would be if it was not compressed, saves and patches are named after it
+    pub logical_path: PathBuf,
+}
+
+pub fn is_rom(path: &Path) -> bool {
+    has_extension(path, &ROM_EXTENSIONS)
+}
+
+pub fn is_loadable(path: &Path) -> bool {
+    is_rom(path) || has_extension(path, &ARCHIVE_EXTENSIONS)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|candidate| ext.eq_ignore_ascii_case(candidate)))
+}
+
+// Loads a plain ROM, or one compressed with gzip or inside a zip archive. Archives hold
+// several files, `entry` picks one by name instead of the first ROM found
+pub fn load(path: &Path, entry: Option<&str>) -> io::Result<RomFile> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("zip") => load_zip(path, entry),
+        Some("gz") => load_gzip(path),
+        _ => Ok(RomFile { blob: fs::read(path)?, logical_path: path.to_path_buf() }),
+    }
+}
+
+fn load_gzip(path: &Path) -> io::Result<RomFile> {
+    let mut blob = Vec::new();
+    GzDecoder::new(File::open(path)?).read_to_end(&mut blob)?;
+
+    // game.gbc.gz holds game.gbc
+    let logical_path = path.with_extension("");
+    info!("Decompressed {} bytes from {}", blob.len(), path.display());
+
+    Ok(RomFile { blob, logical_path })
+}
+
+fn load_zip(path: &Path, entry: Option<&str>) -> io::Result<RomFile> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+    let names: Vec<String> = (0..archive.len())
+        .filter_map(|index| archive.by_index(index).ok().map(|file| file.name().to_owned()))
+        .collect();
+
+    let name = match entry {
+        Some(entry) => names.iter().find(|name| *name == entry || file_name(name) == entry),
+        None => names.iter().find(|name| is_rom(Path::new(name))),
+    };
+    let name = name.cloned().ok_or_else(|| {
+        let wanted = entry.map_or("ROM".to_owned(), |entry| format!("entry {}", entry));
+        io::Error::new(io::ErrorKind::NotFound, format!("No {} found in {}", wanted, path.display()))
+    })?;
+
+    let mut file = archive.by_name(&name)?;
+    let mut blob = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut blob)?;
+
+    // Saves go next to the archive, named after the ROM inside it
+    let logical_path = path.with_file_name(file_name(&name));
+    info!("Extracted {} ({} bytes) from {}", name, blob.len(), path.display());
+
+    Ok(RomFile { blob, logical_path })
+}
+
+fn file_name(entry: &str) -> &str {
+    entry.rsplit('/').next().unwrap_or(entry)
+}
+
+#[cfg(test)]
+mod loader_tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rustboy-loader-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory.join(name)
+    }
+
+    #[test]
+    fn should_load_gzip() {
+        let path = temp_path("game.gbc.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(&[0x12; 0x8000]).unwrap();
+        encoder.finish().unwrap();
+
+        let rom = load(&path, None).unwrap();
+        assert_eq!(rom.blob, vec![0x12; 0x8000]);
+        assert_eq!(rom.logical_path, temp_path("game.gbc"));
+    }
+
+    #[test]
+    fn should_load_zip_entries() {
+        let path = temp_path("collection.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        writer.start_file("readme.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.start_file("roms/first.gb", options).unwrap();
+        writer.write_all(&[0x01; 16]).unwrap();
+        writer.start_file("roms/second.gbc", options).unwrap();
+        writer.write_all(&[0x02; 16]).unwrap();
+        writer.finish().unwrap();
+
+        let rom = load(&path, None).unwrap();
+        assert_eq!(rom.blob, vec![0x01; 16]);
+        assert_eq!(rom.logical_path, temp_path("first.gb"));
+
+        let rom = load(&path, Some("second.gbc")).unwrap();
+        assert_eq!(rom.blob, vec![0x02; 16]);
+
+        assert!(load(&path, Some("third.gb")).is_err());
+    }
+}
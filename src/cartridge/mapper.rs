@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+use log::warn;
+use crate::cartridge::cartridge::{CARTRIDGE_TYPE_LOCATION, NINTENDO_LOGO};
+use crate::cartridge::sachen;
+
+const LOGO_LOCATION: usize = 0x0104;
+const ROM_SIZE_LOCATION: usize = 0x0148;
+const HEADER: std::ops::Range<usize> = 0x0100..0x0150;
+
+// Unlicensed multicarts place each game on a 256KB boundary
+pub const MULTICART_SLOT_SIZE: usize = 0x40000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    PocketCamera,
+    Tama5,
+    Huc3,
+    WisdomTree,
+    Sachen,
+    Multicart,
+}
+
+impl Mapper {
+    // Mapper declared by the header, which unlicensed cartridges do not always tell truthfully
+    pub fn from_header(cartridge_type: u8) -> Option<Mapper> {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => Some(Mapper::RomOnly),
+            0x01..=0x03 => Some(Mapper::Mbc1),
+            0x05 | 0x06 => Some(Mapper::Mbc2),
+            0x0F..=0x13 => Some(Mapper::Mbc3),
+            0x19..=0x1E => Some(Mapper::Mbc5),
+            0x20 => Some(Mapper::Mbc6),
+            0xFC => Some(Mapper::PocketCamera),
+            0xFD => Some(Mapper::Tama5),
+            0xFE => Some(Mapper::Huc3),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Mapper {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "rom" => Ok(Mapper::RomOnly),
+            "mbc1" => Ok(Mapper::Mbc1),
+            "mbc2" => Ok(Mapper::Mbc2),
+            "mbc3" => Ok(Mapper::Mbc3),
+            "mbc5" => Ok(Mapper::Mbc5),
+            "mbc6" => Ok(Mapper::Mbc6),
+            "camera" => Ok(Mapper::PocketCamera),
+            "tama5" => Ok(Mapper::Tama5),
+            "huc3" => Ok(Mapper::Huc3),
+            "wisdom-tree" => Ok(Mapper::WisdomTree),
+            "sachen" => Ok(Mapper::Sachen),
+            "multicart" => Ok(Mapper::Multicart),
+            _ => Err(format!(
+                "Unknown mapper {}, expected one of rom, mbc1, mbc2, mbc3, mbc5, mbc6, camera, tama5, huc3, wisdom-tree, sachen, multicart",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mapper::RomOnly => "ROM ONLY",
+            Mapper::Mbc1 => "MBC1",
+            Mapper::Mbc2 => "MBC2",
+            Mapper::Mbc3 => "MBC3",
+            Mapper::Mbc5 => "MBC5",
+            Mapper::Mbc6 => "MBC6",
+            Mapper::PocketCamera => "POCKET CAMERA",
+            Mapper::Tama5 => "BANDAI TAMA5",
+            Mapper::Huc3 => "HUDSON HUC3",
+            Mapper::WisdomTree => "WISDOM TREE",
+            Mapper::Sachen => "SACHEN",
+            Mapper::Multicart => "MBC5 MULTICART",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Unlicensed cartridges are recognised by the anomalies they leave in the header before
+// trusting the declared cartridge type
+pub fn detect(blob: &[u8]) -> Option<Mapper> {
+    let declared = Mapper::from_header(*blob.get(CARTRIDGE_TYPE_LOCATION)?);
+
+    let detected = if is_sachen(blob) {
+        Some(Mapper::Sachen)
+    } else if is_wisdom_tree(blob) {
+        Some(Mapper::WisdomTree)
+    } else if is_multicart(blob) {
+        Some(Mapper::Multicart)
+    } else {
+        None
+    };
+
+    match (declared, detected) {
+        (declared, Some(detected)) => {
+            warn!("Header declares {:?} mapper but the ROM looks like {}", declared, detected);
+            Some(detected)
+        }
+        (declared, None) => declared,
+    }
+}
+
+fn has_logo_at(blob: &[u8], offset: usize) -> bool {
+    blob.get(offset + LOGO_LOCATION..offset + LOGO_LOCATION + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+}
+
+// Sachen carts only show the Nintendo logo to the boot ROM through scrambled address lines
+fn is_sachen(blob: &[u8]) -> bool {
+    !has_logo_at(blob, 0) && NINTENDO_LOGO.iter().enumerate().all(|(i, &byte)| {
+        let address = sachen::scramble((LOGO_LOCATION + i) as u16) as usize;
+        blob.get(address) == Some(&byte)
+    })
+}
+
+// Wisdom Tree games declare no mapper but are bigger than 32KB, and carry the publisher
+// name. Size alone would catch overdumps and padded homebrew
+fn is_wisdom_tree(blob: &[u8]) -> bool {
+    let declared_rom_only = matches!(blob[CARTRIDGE_TYPE_LOCATION], 0x00 | 0xC0);
+    let signed = blob.windows(11).any(|window| window == b"WISDOM TREE" || window == b"WISDOM\x00TREE");
+
+    declared_rom_only && blob.len() > 0x8000 && signed
+}
+
+// Multicarts declare the size of their menu only, while further games carry their own headers.
+// An overdump mirrors the menu's header instead
+fn is_multicart(blob: &[u8]) -> bool {
+    let declared_size = match blob.get(ROM_SIZE_LOCATION) {
+        Some(&size) if size <= 0x08 => 0x8000 << size,
+        _ => return false,
+    };
+
+    declared_size < blob.len()
+        && (1..blob.len() / MULTICART_SLOT_SIZE).any(|slot| {
+            let offset = slot * MULTICART_SLOT_SIZE;
+            has_logo_at(blob, offset) && blob[offset + HEADER.start..offset + HEADER.end] != blob[HEADER]
+        })
+}
+
+#[cfg(test)]
+mod mapper_tests {
+    use super::*;
+
+    fn rom(size: usize, cartridge_type: u8) -> Vec<u8> {
+        let mut blob = vec![0; size];
+        blob[LOGO_LOCATION..LOGO_LOCATION + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        blob[CARTRIDGE_TYPE_LOCATION] = cartridge_type;
+        blob
+    }
+
+    #[test]
+    fn should_trust_licensed_headers() {
+        assert_eq!(detect(&rom(0x8000, 0x00)), Some(Mapper::RomOnly));
+        assert_eq!(detect(&rom(0x80000, 0x1B)), Some(Mapper::Mbc5));
+        assert_eq!(detect(&rom(0x8000, 0x42)), None);
+    }
+
+    #[test]
+    fn should_detect_wisdom_tree() {
+        let mut blob = rom(0x10000, 0x00);
+        blob[0x134..0x134 + 11].copy_from_slice(b"WISDOM TREE");
+        assert_eq!(detect(&blob), Some(Mapper::WisdomTree));
+
+        blob[CARTRIDGE_TYPE_LOCATION] = 0xC0;
+        blob[0x134..0x134 + 11].copy_from_slice(b"WISDOM\x00TREE");
+        assert_eq!(detect(&blob), Some(Mapper::WisdomTree));
+    }
+
+    #[test]
+    fn should_leave_overdumps_alone() {
+        assert_eq!(detect(&rom(0x10000, 0x00)), Some(Mapper::RomOnly));
+
+        let mut blob = rom(0x8000, 0x00);
+        blob[0x134..0x134 + 11].copy_from_slice(b"WISDOM TREE");
+        assert_eq!(detect(&blob), Some(Mapper::RomOnly));
+    }
+
+    #[test]
+    fn should_detect_sachen() {
+        let mut blob = vec![0; 0x8000];
+        for (i, &byte) in NINTENDO_LOGO.iter().enumerate() {
+            blob[sachen::scramble((LOGO_LOCATION + i) as u16) as usize] = byte;
+        }
+        blob[CARTRIDGE_TYPE_LOCATION] = 0x00;
+
+        assert_eq!(detect(&blob), Some(Mapper::Sachen));
+    }
+
+    #[test]
+    fn should_detect_multicart() {
+        let mut blob = rom(4 * MULTICART_SLOT_SIZE, 0x19);
+        blob[ROM_SIZE_LOCATION] = 0x00;
+        let game = 2 * MULTICART_SLOT_SIZE + LOGO_LOCATION;
+        blob[game..game + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+
+        assert_eq!(detect(&blob), Some(Mapper::Multicart));
+    }
+
+    #[test]
+    fn should_leave_mirrored_overdumps_alone() {
+        let mut game = rom(0x20000, 0x01);
+        game[ROM_SIZE_LOCATION] = 0x02;
+        game[0x4000..0x4100].iter_mut().for_each(|byte| *byte = 0xA5);
+        let blob = game.repeat(4);
+
+        assert_eq!(detect(&blob), Some(Mapper::Mbc1));
+    }
+
+    #[test]
+    fn should_parse_mapper_names() {
+        assert_eq!("Wisdom-Tree".parse::<Mapper>(), Ok(Mapper::WisdomTree));
+        assert!("mbc4".parse::<Mapper>().is_err());
+    }
+}
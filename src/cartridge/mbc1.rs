@@ -1,13 +1,20 @@
-use std::ops;
-use std::ops::{Index, Range};
-use crate::cartridge::cartridge::Cartridge;
+use log::trace;
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::cartridge::header::CartridgeHeader;
+use crate::memory::Address;
 
+// https://gbdev.io/pandocs/MBC1.html
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// In RAM mode the two bit register also switches the RAM bank and the bank at 0x0000-0x3FFF
 enum BankMode {
     ROM, RAM
 }
 
 pub struct Mbc1Cartridge {
     data: Vec<u8>,
+    ram: Vec<u8>,
     current_rom_bank: u8,
     current_ram_bank: u8,
     ram_enabled: bool,
@@ -16,38 +23,103 @@ pub struct Mbc1Cartridge {
 
 impl Mbc1Cartridge {
     pub fn new(blob: Vec<u8>) -> Mbc1Cartridge {
+        let ram_size = CartridgeHeader::parse(&blob).ok().and_then(|header| header.ram_size()).unwrap_or(0);
+
         Mbc1Cartridge {
             data : blob,
-            current_rom_bank: 0,
+            ram: vec![0; ram_size],
+            current_rom_bank: 1,
             current_ram_bank: 0,
             ram_enabled: false,
             bank_mode: BankMode::ROM
         }
     }
-}
 
-impl Cartridge for Mbc1Cartridge {}
+    fn banks(&self) -> (usize, usize, usize) {
+        let upper = (self.current_ram_bank as usize) << 5;
+        match self.bank_mode {
+            BankMode::ROM => (0, upper | self.current_rom_bank as usize, 0),
+            BankMode::RAM => (upper, upper | self.current_rom_bank as usize, self.current_ram_bank as usize),
+        }
+    }
 
-impl ops::Index<u16> for Mbc1Cartridge {
-    type Output = u8;
+    fn rom_offset(&self, bank: usize, address: Address) -> usize {
+        (bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))) % self.data.len()
+    }
 
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.data[index as usize]
+    fn ram_offset(&self, address: Address) -> Option<usize> {
+        let (_, _, ram_bank) = self.banks();
+        let offset = ram_bank * RAM_BANK_SIZE + (address as usize - 0xA000);
+        if self.ram_enabled && !self.ram.is_empty() { Some(offset % self.ram.len()) } else { None }
     }
 }
 
-impl ops::Index<Range<u16>> for Mbc1Cartridge {
-    type Output = [u8];
+impl Cartridge for Mbc1Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        let (rom0, romx, _) = self.banks();
+        match address {
+            0x0000..=0x3FFF => self.data[self.rom_offset(rom0, address)],
+            0x4000..=0x7FFF => self.data[self.rom_offset(romx, address)],
+            0xA000..=0xBFFF => self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset]),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("MBC1 write {:#X} at {:#X}", data, address);
 
-    fn index(&self, index: Range<u16>) -> &Self::Output {
-        let idx = index.start as usize .. index.end as usize;
-        &self.data[idx]
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            // Bank 0 cannot be selected here, it maps bank 1 instead
+            0x2000..=0x3FFF => self.current_rom_bank = (data & 0x1F).max(1),
+            0x4000..=0x5FFF => self.current_ram_bank = data & 0x03,
+            0x6000..=0x7FFF => self.bank_mode = if data & 0x01 == 0 { BankMode::ROM } else { BankMode::RAM },
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn current_banks(&self) -> Banks {
+        let (rom0, romx, ram) = self.banks();
+        Banks { rom0, romx, ram: if self.ram_enabled && !self.ram.is_empty() { Some(ram) } else { None } }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.ram.is_empty() { None } else { Some(&self.ram) }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.ram.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
     }
 }
 
-impl ops::IndexMut<u16> for Mbc1Cartridge {
+#[cfg(test)]
+mod mbc1_tests {
+    use super::*;
+
+    #[test]
+    fn should_map_upper_bits_in_ram_mode() {
+        let mut blob = vec![0; 128 * ROM_BANK_SIZE];
+        blob[0x21 * ROM_BANK_SIZE] = 0x21;
+        blob[0x20 * ROM_BANK_SIZE] = 0x20;
+        let mut cartridge = Mbc1Cartridge::new(blob);
 
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        &mut self.data[index as usize]
+        cartridge.write(0x2000, 0x00);
+        cartridge.write(0x4000, 0x01);
+        assert_eq!(cartridge.read(0x4000), 0x21);
+        assert_eq!(cartridge.read(0x0000), 0x00);
+
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0x0000), 0x20);
+        assert_eq!(cartridge.current_banks(), Banks { rom0: 0x20, romx: 0x21, ram: None });
     }
-}
\ No newline at end of file
+}
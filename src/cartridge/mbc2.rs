@@ -1,40 +1,65 @@
-use core::ops;
-use crate::cartridge::cartridge::Cartridge;
-use std::ops::Range;
+use log::trace;
+use crate::cartridge::cartridge::{Banks, Cartridge};
 use crate::memory::Address;
 
+// https://gbdev.io/pandocs/MBC2.html
+// 512 half bytes of RAM are built in, echoed over the whole 0xA000-0xBFFF area. Address
+// bit 8 tells RAM enable writes from ROM bank writes
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_SIZE: usize = 512;
+
 pub struct Mbc2Cartridge {
     data: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    current_rom_bank: usize,
+    ram_enabled: bool,
 }
 
 impl Mbc2Cartridge {
     pub fn new(data: Vec<u8>) -> Mbc2Cartridge {
-        Mbc2Cartridge { data }
+        Mbc2Cartridge { data, ram: [0; RAM_SIZE], current_rom_bank: 1, ram_enabled: false }
     }
 }
 
-impl Cartridge for Mbc2Cartridge {}
+impl Cartridge for Mbc2Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.current_rom_bank * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+                self.data[offset % self.data.len()]
+            }
+            // Only the lower nibble exists, the upper one floats high
+            0xA000..=0xBFFF if self.ram_enabled => 0xF0 | self.ram[address as usize % RAM_SIZE],
+            _ => 0xFF,
+        }
+    }
 
-impl ops::Index<u16> for Mbc2Cartridge {
-    type Output = u8;
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("MBC2 write {:#X} at {:#X}", data, address);
 
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.data[index as usize]
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = data & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.current_rom_bank = (data as usize & 0x0F).max(1),
+            0xA000..=0xBFFF if self.ram_enabled => self.ram[address as usize % RAM_SIZE] = data & 0x0F,
+            _ => {}
+        }
     }
-}
 
-impl ops::Index<Range<u16>> for Mbc2Cartridge {
-    type Output = [u8];
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 0, romx: self.current_rom_bank, ram: if self.ram_enabled { Some(0) } else { None } }
+    }
 
-    fn index(&self, index: Range<u16>) -> &Self::Output {
-        let idx = index.start as usize .. index.end as usize;
-        &self.data[idx]
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
     }
-}
 
-impl ops::IndexMut<u16> for Mbc2Cartridge {
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(RAM_SIZE);
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
 
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        &mut self.data[index as usize]
+    fn rom(&self) -> &[u8] {
+        &self.data
     }
-}
\ No newline at end of file
+}
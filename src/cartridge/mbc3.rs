@@ -1,40 +1,204 @@
-use core::ops;
-use crate::cartridge::cartridge::Cartridge;
-use std::ops::Range;
+use log::{trace, warn};
+use crate::cartridge::cartridge::{Banks, Cartridge, CARTRIDGE_TYPE_LOCATION};
+use crate::cartridge::clock::{Clock, SystemClock};
+use crate::cartridge::header::CartridgeHeader;
+use crate::cartridge::rtc::{ClockFooter, RealTimeClock};
 use crate::memory::Address;
 
+// https://gbdev.io/pandocs/MBC3.html
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// Values of 0x4000-0x5FFF mapping a clock register instead of a RAM bank
+const FIRST_RTC_REGISTER: u8 = 0x08;
+const LAST_RTC_REGISTER: u8 = 0x0C;
+
 pub struct Mbc3Cartridge {
     data: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<RealTimeClock>,
+    current_rom_bank: usize,
+    // RAM bank, or clock register when 0x08 or above
+    current_ram_bank: u8,
+    ram_enabled: bool,
+    // Without a battery the RAM is lost at power off, there is nothing to save
+    battery: bool,
 }
 
 impl Mbc3Cartridge {
     pub fn new(data: Vec<u8>) -> Mbc3Cartridge {
-        Mbc3Cartridge { data }
+        let ram_size = CartridgeHeader::parse(&data).ok().and_then(|header| header.ram_size()).unwrap_or(0);
+        // MBC3+TIMER and MBC3+TIMER+RAM+BATTERY
+        let rtc = match data.get(CARTRIDGE_TYPE_LOCATION) {
+            Some(0x0F) | Some(0x10) => Some(RealTimeClock::new(Box::new(SystemClock::default()))),
+            _ => None,
+        };
+        let battery = matches!(data.get(CARTRIDGE_TYPE_LOCATION), Some(0x0F) | Some(0x10) | Some(0x13));
+
+        Mbc3Cartridge {
+            data,
+            ram: vec![0; ram_size],
+            rtc,
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            ram_enabled: false,
+            battery,
+        }
+    }
+
+    fn rtc_register(&self) -> Option<usize> {
+        match self.current_ram_bank {
+            FIRST_RTC_REGISTER..=LAST_RTC_REGISTER if self.rtc.is_some() => {
+                Some((self.current_ram_bank - FIRST_RTC_REGISTER) as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn ram_offset(&self, address: Address) -> Option<usize> {
+        let offset = self.current_ram_bank as usize * RAM_BANK_SIZE + (address as usize - 0xA000);
+        if self.ram_enabled && offset < self.ram.len() { Some(offset) } else { None }
     }
 }
 
-impl Cartridge for Mbc3Cartridge {}
+impl Cartridge for Mbc3Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.current_rom_bank * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+                self.data[offset % self.data.len()]
+            }
+            0xA000..=0xBFFF if self.ram_enabled => match (self.rtc_register(), self.ram_offset(address)) {
+                (Some(register), _) => self.rtc.as_ref().unwrap().latched(register),
+                (None, Some(offset)) => self.ram[offset],
+                (None, None) => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
 
-impl ops::Index<u16> for Mbc3Cartridge {
-    type Output = u8;
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("MBC3 write {:#X} at {:#X}", data, address);
 
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.data[index as usize]
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            // MBC30 decodes all 8 bits, bank 0 maps bank 1 instead
+            0x2000..=0x3FFF => self.current_rom_bank = (data as usize).max(1),
+            0x4000..=0x5FFF => self.current_ram_bank = data & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(data);
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                if let Some(register) = self.rtc_register() {
+                    self.rtc.as_mut().unwrap().write(register, data);
+                } else if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
     }
-}
 
-impl ops::Index<Range<u16>> for Mbc3Cartridge {
-    type Output = [u8];
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn current_banks(&self) -> Banks {
+        let ram = match self.rtc_register() {
+            None if self.ram_enabled && !self.ram.is_empty() => Some(self.current_ram_bank as usize),
+            _ => None,
+        };
+        Banks { rom0: 0, romx: self.current_rom_bank, ram }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if !self.battery || self.ram.is_empty() { None } else { Some(&self.ram) }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.ram.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
 
-    fn index(&self, index: Range<u16>) -> &Self::Output {
-        let idx = index.start as usize .. index.end as usize;
-        &self.data[idx]
+    fn save_rtc(&self) -> Option<ClockFooter> {
+        self.rtc.as_ref().map(|rtc| ClockFooter::Mbc3(rtc.footer()))
+    }
+
+    fn load_rtc(&mut self, footer: &ClockFooter) {
+        if let Some(rtc) = &mut self.rtc {
+            match footer.mbc3() {
+                Some(footer) => rtc.load_footer(&footer),
+                None => warn!("Ignoring a clock footer saved by another mapper"),
+            }
+        }
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_source(clock);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
     }
 }
 
-impl ops::IndexMut<u16> for Mbc3Cartridge {
+#[cfg(test)]
+mod mbc3_tests {
+    use super::*;
+
+    fn rom(cartridge_type: u8) -> Vec<u8> {
+        let mut blob = vec![0; 8 * ROM_BANK_SIZE];
+        blob[CARTRIDGE_TYPE_LOCATION] = cartridge_type;
+        // 32KB of RAM
+        blob[0x0149] = 0x03;
+        blob
+    }
+
+    #[test]
+    fn should_switch_rom_and_ram_banks() {
+        let mut blob = rom(0x13);
+        blob[5 * ROM_BANK_SIZE] = 0x55;
+        let mut cartridge = Mbc3Cartridge::new(blob);
 
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        &mut self.data[index as usize]
+        cartridge.write(0x2000, 5);
+        assert_eq!(cartridge.read(0x4000), 0x55);
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 2);
+        cartridge.write(0xA010, 0x42);
+        assert_eq!(cartridge.ram[2 * RAM_BANK_SIZE + 0x10], 0x42);
+        assert!(cartridge.save_rtc().is_none());
+    }
+
+    #[test]
+    fn should_only_save_battery_backed_ram() {
+        assert!(Mbc3Cartridge::new(rom(0x13)).save_ram().is_some());
+        assert!(Mbc3Cartridge::new(rom(0x10)).save_ram().is_some());
+        assert!(Mbc3Cartridge::new(rom(0x12)).save_ram().is_none());
+    }
+
+    #[test]
+    fn should_latch_clock_registers() {
+        let mut cartridge = Mbc3Cartridge::new(rom(0x10));
+        cartridge.write(0x0000, 0x0A);
+
+        // Halt the clock so it cannot tick between the write and the latch
+        cartridge.write(0x4000, 0x0C);
+        cartridge.write(0xA000, 0x40);
+        cartridge.write(0x4000, 0x0A);
+        cartridge.write(0xA000, 17);
+        assert_eq!(cartridge.read(0xA000), 0);
+
+        cartridge.write(0x6000, 0);
+        cartridge.write(0x6000, 1);
+        assert_eq!(cartridge.read(0xA000), 17);
+        assert_eq!(cartridge.save_rtc().unwrap().mbc3().unwrap().registers[2], 17);
     }
-}
\ No newline at end of file
+}
@@ -1,40 +1,138 @@
-use crate::cartridge::cartridge::Cartridge;
-use core::ops;
-use std::ops::Range;
+use log::trace;
+use crate::cartridge::cartridge::{Banks, Cartridge, CARTRIDGE_TYPE_LOCATION};
+use crate::cartridge::header::CartridgeHeader;
 use crate::memory::Address;
 
+// https://gbdev.io/pandocs/MBC5.html
+// Unlike the older mappers bank 0 can be mapped at 0x4000, and the ROM bank takes 9 bits
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
 pub struct Mbc5Cartridge {
     data: Vec<u8>,
+    ram: Vec<u8>,
+    current_rom_bank: usize,
+    current_ram_bank: usize,
+    ram_enabled: bool,
+    // Types 0x1C-0x1E, bit 3 of the RAM bank register drives the motor
+    rumble: bool,
+    // Without a battery the RAM is lost at power off, there is nothing to save
+    battery: bool,
 }
 
 impl Mbc5Cartridge {
     pub fn new(data: Vec<u8>) -> Mbc5Cartridge {
-        Mbc5Cartridge { data }
+        let ram_size = CartridgeHeader::parse(&data).ok().and_then(|header| header.ram_size()).unwrap_or(0);
+        let rumble = matches!(data.get(CARTRIDGE_TYPE_LOCATION), Some(0x1C..=0x1E));
+        let battery = matches!(data.get(CARTRIDGE_TYPE_LOCATION), Some(0x1B) | Some(0x1E));
+
+        Mbc5Cartridge {
+            data,
+            ram: vec![0; ram_size],
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            ram_enabled: false,
+            rumble,
+            battery,
+        }
+    }
+
+    fn ram_offset(&self, address: Address) -> Option<usize> {
+        let offset = self.current_ram_bank * RAM_BANK_SIZE + (address as usize - 0xA000);
+        if self.ram_enabled && offset < self.ram.len() { Some(offset) } else { None }
     }
 }
 
-impl Cartridge for Mbc5Cartridge {}
+impl Cartridge for Mbc5Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.current_rom_bank * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+                self.data[offset % self.data.len()]
+            }
+            0xA000..=0xBFFF => self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset]),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("MBC5 write {:#X} at {:#X}", data, address);
 
-impl ops::Index<u16> for Mbc5Cartridge {
-    type Output = u8;
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.current_rom_bank = (self.current_rom_bank & 0x100) | data as usize,
+            0x3000..=0x3FFF => self.current_rom_bank = (self.current_rom_bank & 0xFF) | ((data as usize & 0x01) << 8),
+            0x4000..=0x5FFF => {
+                let mask = if self.rumble { 0x07 } else { 0x0F };
+                if self.rumble {
+                    trace!("Rumble motor {}", if data & 0x08 != 0 { "on" } else { "off" });
+                }
+                self.current_ram_bank = (data & mask) as usize;
+            }
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+    }
 
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.data[index as usize]
+    fn current_banks(&self) -> Banks {
+        let ram = if self.ram_enabled && !self.ram.is_empty() { Some(self.current_ram_bank) } else { None };
+        Banks { rom0: 0, romx: self.current_rom_bank, ram }
     }
-}
 
-impl ops::Index<Range<u16>> for Mbc5Cartridge {
-    type Output = [u8];
+    fn save_ram(&self) -> Option<&[u8]> {
+        if !self.battery || self.ram.is_empty() { None } else { Some(&self.ram) }
+    }
 
-    fn index(&self, index: Range<u16>) -> &Self::Output {
-        let idx = index.start as usize .. index.end as usize;
-        &self.data[idx]
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.ram.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
     }
 }
 
-impl ops::IndexMut<u16> for Mbc5Cartridge {
+#[cfg(test)]
+mod mbc5_tests {
+    use super::*;
 
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        &mut self.data[index as usize]
+    fn cartridge(cartridge_type: u8) -> Mbc5Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[CARTRIDGE_TYPE_LOCATION] = cartridge_type;
+        rom[0x149] = 0x04;
+        let mut cartridge = Mbc5Cartridge::new(rom);
+        cartridge.write(0x0000, 0x0A);
+        cartridge
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_keep_motor_bit_out_of_ram_bank() {
+        let mut rumble = cartridge(0x1E);
+        rumble.write(0x4000, 0x08 | 0x03);
+        assert_eq!(rumble.current_banks().ram, Some(3));
+
+        let mut plain = cartridge(0x1B);
+        plain.write(0x4000, 0x08 | 0x03);
+        assert_eq!(plain.current_banks().ram, Some(11));
+    }
+
+    #[test]
+    fn should_only_save_battery_backed_ram() {
+        assert!(cartridge(0x1B).save_ram().is_some());
+        assert!(cartridge(0x1E).save_ram().is_some());
+        assert!(cartridge(0x1A).save_ram().is_none());
+        assert!(cartridge(0x1D).save_ram().is_none());
+    }
+
+    #[test]
+    fn should_float_reads_past_short_images() {
+        let cartridge = Mbc5Cartridge::new(vec![0; 0x150]);
+        assert_eq!(cartridge.read(0x0200), 0xFF);
+    }
+}
@@ -0,0 +1,277 @@
+use log::{debug, trace};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::memory::Address;
+
+// https://gbdev.io/pandocs/MBC6.html
+// Two independent 8KB windows at 0x4000 and 0x6000, each mapping either ROM or flash,
+// and two independent 4KB RAM windows at 0xA000 and 0xB000
+const WINDOW_SIZE: usize = 0x2000;
+const RAM_WINDOW_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 8 * RAM_WINDOW_SIZE;
+const FLASH_SIZE: usize = 128 * WINDOW_SIZE;
+const FLASH_SECTOR_SIZE: usize = 0x10000;
+
+// Macronix MX29F008, manufacturer and device codes
+const FLASH_ID: [u8; 2] = [0xC2, 0x81];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Identify,
+    Program,
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+#[derive(Clone, Copy)]
+struct Window {
+    bank: usize,
+    flash: bool,
+}
+
+pub struct Mbc6Cartridge {
+    data: Vec<u8>,
+    // RAM followed by flash, both are persisted together
+    battery: Vec<u8>,
+    windows: [Window; 2],
+    ram_banks: [usize; 2],
+    ram_enabled: bool,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+}
+
+impl Mbc6Cartridge {
+    pub fn new(blob: Vec<u8>) -> Mbc6Cartridge {
+        let mut battery = vec![0; RAM_SIZE + FLASH_SIZE];
+        // Erased flash reads as all ones
+        battery[RAM_SIZE..].iter_mut().for_each(|byte| *byte = 0xFF);
+
+        Mbc6Cartridge {
+            data: blob,
+            battery,
+            windows: [Window { bank: 0, flash: false }; 2],
+            ram_banks: [0; 2],
+            ram_enabled: false,
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Read,
+        }
+    }
+
+    fn window(address: Address) -> usize {
+        (address as usize - 0x4000) / WINDOW_SIZE
+    }
+
+    fn flash_offset(&self, address: Address) -> usize {
+        let window = self.windows[Mbc6Cartridge::window(address)];
+        (window.bank * WINDOW_SIZE + (address as usize & (WINDOW_SIZE - 1))) % FLASH_SIZE
+    }
+
+    fn ram_offset(&self, address: Address) -> usize {
+        let window = (address as usize - 0xA000) / RAM_WINDOW_SIZE;
+        self.ram_banks[window] * RAM_WINDOW_SIZE + (address as usize & (RAM_WINDOW_SIZE - 1))
+    }
+
+    // JEDEC command sequences, unlock addresses are decoded on the lower 15 bits
+    fn flash_command(&mut self, offset: usize, data: u8) {
+        use FlashState::*;
+
+        self.flash_state = match (self.flash_state, offset & 0x7FFF, data) {
+            (_, _, 0xF0) => Read,
+            (Read, 0x5555, 0xAA) | (Identify, 0x5555, 0xAA) => Unlock1,
+            (Unlock1, 0x2AAA, 0x55) => Unlock2,
+            (Unlock2, 0x5555, 0x90) => Identify,
+            (Unlock2, 0x5555, 0xA0) => Program,
+            (Unlock2, 0x5555, 0x80) => Erase,
+            (Erase, 0x5555, 0xAA) => EraseUnlock1,
+            (EraseUnlock1, 0x2AAA, 0x55) => EraseUnlock2,
+            (EraseUnlock2, 0x5555, 0x10) => {
+                if self.flash_write_enabled {
+                    debug!("Erasing MBC6 flash");
+                    self.battery[RAM_SIZE..].iter_mut().for_each(|byte| *byte = 0xFF);
+                }
+                Read
+            }
+            (EraseUnlock2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let sector = RAM_SIZE + offset / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                    debug!("Erasing MBC6 flash sector at {:#X}", sector - RAM_SIZE);
+                    self.battery[sector..sector + FLASH_SECTOR_SIZE].iter_mut().for_each(|byte| *byte = 0xFF);
+                }
+                Read
+            }
+            (Program, _, _) => {
+                // Programming can only clear bits, erasing sets them back
+                if self.flash_write_enabled {
+                    self.battery[RAM_SIZE + offset] &= data;
+                }
+                Read
+            }
+            (state, _, _) => {
+                debug!("Unexpected MBC6 flash write {:#X} at {:#X} in state {:?}", data, offset, state);
+                Read
+            }
+        };
+    }
+}
+
+impl Cartridge for Mbc6Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let window = self.windows[Mbc6Cartridge::window(address)];
+                let offset = window.bank * WINDOW_SIZE + (address as usize & (WINDOW_SIZE - 1));
+
+                // A window mapped to the flash chip floats while 0x0C00 keeps it off the bus
+                match (window.flash, self.flash_state) {
+                    (true, _) if !self.flash_enabled => 0xFF,
+                    (true, FlashState::Identify) => FLASH_ID[offset & 0x01],
+                    (true, _) => self.battery[RAM_SIZE + self.flash_offset(address)],
+                    (false, _) => self.data[offset % self.data.len()],
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => self.battery[self.ram_offset(address)],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("MBC6 write {:#X} at {:#X}", data, address);
+
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = (data & 0x07) as usize,
+            0x0800..=0x0BFF => self.ram_banks[1] = (data & 0x07) as usize,
+            0x0C00..=0x0FFF => self.flash_enabled = data & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = data & 0x01 != 0,
+            0x2000..=0x27FF => self.windows[0].bank = (data & 0x7F) as usize,
+            0x2800..=0x2FFF => self.windows[0].flash = data == 0x08,
+            0x3000..=0x37FF => self.windows[1].bank = (data & 0x7F) as usize,
+            0x3800..=0x3FFF => self.windows[1].flash = data == 0x08,
+            0x4000..=0x7FFF if self.flash_enabled && self.windows[Mbc6Cartridge::window(address)].flash => {
+                let offset = self.flash_offset(address);
+                self.flash_command(offset, data);
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                let offset = self.ram_offset(address);
+                self.battery[offset] = data;
+            }
+            _ => {}
+        }
+    }
+
+    // Only the first of each pair of windows fits, in 8KB and 4KB banks
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 0, romx: self.windows[0].bank, ram: if self.ram_enabled { Some(self.ram_banks[0]) } else { None } }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.battery)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(self.battery.len());
+        self.battery[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod mbc6_tests {
+    use super::*;
+
+    // Flash bank 2 offset 0x1555 and bank 1 offset 0x0AAA decode as 0x5555 and 0x2AAA
+    fn flash_sequence(cartridge: &mut Mbc6Cartridge, commands: &[u8]) {
+        for (step, &command) in commands.iter().enumerate() {
+            if step % 3 == 1 {
+                cartridge.write(0x2000, 0x01);
+                cartridge.write(0x4AAA, command);
+            } else {
+                cartridge.write(0x2000, 0x02);
+                cartridge.write(0x5555, command);
+            }
+        }
+    }
+
+    fn flash_cartridge() -> Mbc6Cartridge {
+        let mut cartridge = Mbc6Cartridge::new(vec![0; 0x10000]);
+        cartridge.write(0x0C00, 0x01);
+        cartridge.write(0x1000, 0x01);
+        cartridge.write(0x2800, 0x08);
+        cartridge.write(0x3800, 0x08);
+        cartridge
+    }
+
+    #[test]
+    fn should_map_independent_rom_windows() {
+        let mut blob = vec![0; 0x10000];
+        blob[3 * WINDOW_SIZE] = 0x33;
+        blob[5 * WINDOW_SIZE] = 0x55;
+        let mut cartridge = Mbc6Cartridge::new(blob);
+
+        cartridge.write(0x2000, 3);
+        cartridge.write(0x3000, 5);
+
+        assert_eq!(cartridge.read(0x4000), 0x33);
+        assert_eq!(cartridge.read(0x6000), 0x55);
+    }
+
+    #[test]
+    fn should_map_independent_ram_windows() {
+        let mut cartridge = Mbc6Cartridge::new(vec![0; 0x10000]);
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x0400, 2);
+        cartridge.write(0x0800, 2);
+
+        cartridge.write(0xA010, 0x77);
+        assert_eq!(cartridge.read(0xB010), 0x77);
+    }
+
+    #[test]
+    fn should_identify_flash() {
+        let mut cartridge = flash_cartridge();
+
+        flash_sequence(&mut cartridge, &[0xAA, 0x55, 0x90]);
+        assert_eq!(cartridge.read(0x6000), 0xC2);
+        assert_eq!(cartridge.read(0x6001), 0x81);
+
+        cartridge.write(0x6000, 0xF0);
+        assert_eq!(cartridge.read(0x6000), 0xFF);
+    }
+
+    #[test]
+    fn should_float_flash_windows_while_flash_is_disabled() {
+        let mut cartridge = flash_cartridge();
+        flash_sequence(&mut cartridge, &[0xAA, 0x55, 0x90]);
+        cartridge.write(0x0C00, 0x00);
+
+        assert_eq!(cartridge.read(0x6000), 0xFF);
+        assert_eq!(cartridge.read(0x6001), 0xFF);
+
+        cartridge.write(0x0C00, 0x01);
+        assert_eq!(cartridge.read(0x6000), 0xC2);
+    }
+
+    #[test]
+    fn should_program_and_erase_flash() {
+        let mut cartridge = flash_cartridge();
+
+        flash_sequence(&mut cartridge, &[0xAA, 0x55, 0xA0]);
+        cartridge.write(0x3000, 0x04);
+        cartridge.write(0x6123, 0x3C);
+        assert_eq!(cartridge.read(0x6123), 0x3C);
+        assert_eq!(cartridge.save_ram().unwrap()[RAM_SIZE + 4 * WINDOW_SIZE + 0x123], 0x3C);
+
+        flash_sequence(&mut cartridge, &[0xAA, 0x55, 0x80, 0xAA, 0x55]);
+        cartridge.write(0x6000, 0x30);
+        assert_eq!(cartridge.read(0x6123), 0xFF);
+    }
+}
@@ -1,6 +1,23 @@
 pub mod cartridge;
 pub mod rom;
+pub mod camera;
+pub mod image_source;
+pub mod save;
+pub mod mapper;
+pub mod header;
+pub mod sachen;
+pub mod loader;
+pub mod patch;
+pub mod database;
+pub mod rtc;
+pub mod clock;
+pub mod empty;
 mod mbc1;
 mod mbc2;
 mod mbc3;
-mod mbc5;
\ No newline at end of file
+mod mbc5;
+mod mbc6;
+mod tama5;
+mod huc3;
+mod wisdom_tree;
+mod multicart;
\ No newline at end of file
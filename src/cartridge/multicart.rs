@@ -0,0 +1,120 @@
+use log::{debug, trace};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::cartridge::mapper::MULTICART_SLOT_SIZE;
+use crate::memory::Address;
+
+// Pirate multicarts wrap a regular MBC5 with an outer register at 0x6000-0x7FFF, unused
+// by MBC5 itself. It picks the 256KB slot the selected game starts at, and writing it
+// with bit 7 set locks the choice until power off
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const RAM_SIZE: usize = 16 * RAM_BANK_SIZE;
+const BANKS_PER_SLOT: usize = MULTICART_SLOT_SIZE / ROM_BANK_SIZE;
+const LOCK: u8 = 0x80;
+
+pub struct MulticartCartridge {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    slot: usize,
+    locked: bool,
+    current_rom_bank: usize,
+    current_ram_bank: usize,
+    ram_enabled: bool,
+}
+
+impl MulticartCartridge {
+    pub fn new(data: Vec<u8>) -> MulticartCartridge {
+        MulticartCartridge {
+            data,
+            ram: vec![0; RAM_SIZE],
+            slot: 0,
+            locked: false,
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            ram_enabled: false,
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, address: Address) -> usize {
+        let bank = self.slot * BANKS_PER_SLOT + bank;
+        (bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))) % self.data.len()
+    }
+
+    fn ram_offset(&self, address: Address) -> usize {
+        self.current_ram_bank * RAM_BANK_SIZE + (address as usize - 0xA000)
+    }
+}
+
+impl Cartridge for MulticartCartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data[self.rom_offset(0, address)],
+            0x4000..=0x7FFF => self.data[self.rom_offset(self.current_rom_bank, address)],
+            0xA000..=0xBFFF if self.ram_enabled => self.ram[self.ram_offset(address)],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("Multicart write {:#X} at {:#X}", data, address);
+
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.current_rom_bank = (self.current_rom_bank & 0x100) | data as usize,
+            0x3000..=0x3FFF => self.current_rom_bank = (self.current_rom_bank & 0xFF) | ((data as usize & 0x01) << 8),
+            0x4000..=0x5FFF => self.current_ram_bank = (data & 0x0F) as usize,
+            0x6000..=0x7FFF if !self.locked => {
+                self.slot = (data & !LOCK) as usize;
+                self.locked = data & LOCK != 0;
+                debug!("Multicart slot {} selected, locked: {}", self.slot, self.locked);
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                let offset = self.ram_offset(address);
+                self.ram[offset] = data;
+            }
+            _ => {}
+        }
+    }
+
+    // Banks are absolute within the ROM, the slot offset included
+    fn current_banks(&self) -> Banks {
+        let first = self.slot * BANKS_PER_SLOT;
+        let ram = if self.ram_enabled { Some(self.current_ram_bank) } else { None };
+        Banks { rom0: first, romx: first + self.current_rom_bank, ram }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(RAM_SIZE);
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod multicart_tests {
+    use super::*;
+
+    #[test]
+    fn should_lock_selected_game() {
+        let mut blob = vec![0; 4 * MULTICART_SLOT_SIZE];
+        blob[2 * MULTICART_SLOT_SIZE] = 0x20;
+        blob[2 * MULTICART_SLOT_SIZE + 3 * ROM_BANK_SIZE] = 0x23;
+        blob[3 * MULTICART_SLOT_SIZE] = 0x30;
+        let mut cartridge = MulticartCartridge::new(blob);
+
+        cartridge.write(0x6000, LOCK | 2);
+        cartridge.write(0x2000, 3);
+        assert_eq!(cartridge.read(0x0000), 0x20);
+        assert_eq!(cartridge.read(0x4000), 0x23);
+
+        cartridge.write(0x6000, 3);
+        assert_eq!(cartridge.read(0x0000), 0x20);
+    }
+}
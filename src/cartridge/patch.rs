@@ -0,0 +1,457 @@
+use std::error;
+use std::fmt;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use log::info;
+
+// Soft patches are looked up next to the ROM, sharing its name
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// UPS and BPS end with the CRC32 of the source, the target and the patch itself
+const FOOTER_SIZE: usize = 12;
+
+// BPS actions, held in the two low bits of each command
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+// Well past the biggest multicart, anything larger is a corrupted or crafted size
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,
+    OutOfRange,
+    SourceSizeMismatch { expected: usize, actual: usize },
+    SourceChecksumMismatch { expected: u32, actual: u32 },
+    TargetChecksumMismatch { expected: u32, actual: u32 },
+    PatchChecksumMismatch { expected: u32, actual: u32 },
+}
+
+pub fn find(rom: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS.iter()
+        .map(|extension| rom.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+// Patches the ROM in memory, the format is told by the magic number rather than the extension
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let target = if patch.starts_with(IPS_MAGIC) {
+        apply_ips(source, patch)?
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(source, patch)?
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(source, patch)?
+    } else {
+        return Err(PatchError::UnknownFormat);
+    };
+
+    info!("Patched ROM from {} to {} bytes", source.len(), target.len());
+    Ok(target)
+}
+
+// https://zerosoft.zophar.net/ips.php
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.peek(IPS_EOF.len())? == IPS_EOF {
+            reader.skip(IPS_EOF.len());
+            break;
+        }
+
+        let offset = reader.big_endian(3)?;
+        let size = reader.big_endian(2)?;
+
+        // A zero sized record is run length encoded
+        let (size, data) = match size {
+            0 => {
+                let size = reader.big_endian(2)?;
+                (size, vec![reader.byte()?; size])
+            }
+            _ => (size, reader.bytes(size)?.to_vec()),
+        };
+
+        if target.len() < offset + size {
+            target.resize(offset + size, 0);
+        }
+        target[offset..offset + size].copy_from_slice(&data);
+    }
+
+    // Lunar IPS extension, the final size follows the end marker
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+
+    Ok(target)
+}
+
+// http://individual.utoronto.ca/dmeunier/ups-spec.pdf
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = Footer::read(source, patch)?;
+    let mut reader = Reader::new(&patch[..patch.len() - FOOTER_SIZE], UPS_MAGIC.len());
+
+    let source_size = reader.number()? as usize;
+    let target_size = reader.size()?;
+    check_source_size(source, source_size)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+
+    // Hunks skip unchanged bytes, then XOR until a zero byte ends them
+    let mut offset = 0;
+    while !reader.is_empty() {
+        offset = span(offset as i64, reader.number()? as usize)?.end;
+        loop {
+            let xor = reader.byte()?;
+            if xor == 0 {
+                offset += 1;
+                break;
+            }
+            if let Some(byte) = target.get_mut(offset) {
+                *byte = source.get(offset).copied().unwrap_or(0) ^ xor;
+            }
+            offset += 1;
+        }
+    }
+
+    footer.check_target(&target)?;
+    Ok(target)
+}
+
+// https://github.com/blakesmith/rombp/blob/master/docs/bps_spec.md
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = Footer::read(source, patch)?;
+    let mut reader = Reader::new(&patch[..patch.len() - FOOTER_SIZE], BPS_MAGIC.len());
+
+    let source_size = reader.number()? as usize;
+    let target_size = reader.size()?;
+    check_source_size(source, source_size)?;
+
+    let metadata_size = reader.number()? as usize;
+    reader.bytes(metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: i64 = 0;
+    let mut target_offset: i64 = 0;
+
+    while !reader.is_empty() {
+        if target.len() > target_size {
+            return Err(PatchError::OutOfRange);
+        }
+        let command = reader.number()?;
+        let length = (command >> 2) as usize + 1;
+
+        match command & 0x03 {
+            SOURCE_READ => {
+                let span = span(target.len() as i64, length)?;
+                target.extend_from_slice(source.get(span).ok_or(PatchError::Truncated)?);
+            }
+            TARGET_READ => target.extend_from_slice(reader.bytes(length)?),
+            SOURCE_COPY => {
+                source_offset = source_offset.checked_add(reader.signed_number()?).ok_or(PatchError::OutOfRange)?;
+                let span = span(source_offset, length)?;
+                source_offset = span.end as i64;
+                target.extend_from_slice(source.get(span).ok_or(PatchError::Truncated)?);
+            }
+            TARGET_COPY => {
+                target_offset = target_offset.checked_add(reader.signed_number()?).ok_or(PatchError::OutOfRange)?;
+                span(target_offset, length)?;
+                // Copies may overlap the bytes they produce, which repeats a pattern
+                for _ in 0..length {
+                    let byte = *target.get(target_offset as usize).ok_or(PatchError::Truncated)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+
+    footer.check_target(&target)?;
+    Ok(target)
+}
+
+// Bounds of a copy or a skip, which crafted patches can point anywhere
+fn span(start: i64, length: usize) -> Result<Range<usize>, PatchError> {
+    let start = usize::try_from(start).map_err(|_| PatchError::OutOfRange)?;
+    match start.checked_add(length) {
+        Some(end) if end <= MAX_TARGET_SIZE => Ok(start..end),
+        _ => Err(PatchError::OutOfRange),
+    }
+}
+
+fn check_source_size(source: &[u8], expected: usize) -> Result<(), PatchError> {
+    match source.len() {
+        actual if actual != expected => Err(PatchError::SourceSizeMismatch { expected, actual }),
+        _ => Ok(()),
+    }
+}
+
+struct Footer {
+    target_crc: u32,
+}
+
+impl Footer {
+    // The source and the patch are checked upfront, the target once it has been built
+    fn read(source: &[u8], patch: &[u8]) -> Result<Footer, PatchError> {
+        if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+            return Err(PatchError::Truncated);
+        }
+
+        let footer = &patch[patch.len() - FOOTER_SIZE..];
+        let word = |index: usize| u32::from_le_bytes([footer[index], footer[index + 1], footer[index + 2], footer[index + 3]]);
+
+        let expected = word(8);
+        let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+        if expected != actual {
+            return Err(PatchError::PatchChecksumMismatch { expected, actual });
+        }
+
+        let expected = word(0);
+        let actual = crc32fast::hash(source);
+        if expected != actual {
+            return Err(PatchError::SourceChecksumMismatch { expected, actual });
+        }
+
+        Ok(Footer { target_crc: word(4) })
+    }
+
+    fn check_target(&self, target: &[u8]) -> Result<(), PatchError> {
+        match crc32fast::hash(target) {
+            actual if actual != self.target_crc => {
+                Err(PatchError::TargetChecksumMismatch { expected: self.target_crc, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Reader<'a> {
+        Reader { data, position }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn skip(&mut self, length: usize) {
+        self.position += length;
+    }
+
+    fn peek(&self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.position.checked_add(length).ok_or(PatchError::Truncated)?;
+        self.data.get(self.position..end).ok_or(PatchError::Truncated)
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self.peek(length)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, length: usize) -> Result<usize, PatchError> {
+        Ok(self.bytes(length)?.iter().fold(0, |value, &byte| (value << 8) | byte as usize))
+    }
+
+    // UPS and BPS variable length integers, 7 bits at a time with the last byte flagged
+    fn number(&mut self) -> Result<u64, PatchError> {
+        let mut value = 0u64;
+        let mut shift = 1u64;
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as u64).checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or(PatchError::OutOfRange)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfRange)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfRange)?;
+        }
+    }
+
+    // Target sizes are allocated upfront, they are checked before that
+    fn size(&mut self) -> Result<usize, PatchError> {
+        match self.number()? as usize {
+            size if size > MAX_TARGET_SIZE => Err(PatchError::OutOfRange),
+            size => Ok(size),
+        }
+    }
+
+    // BPS relative offsets keep their sign in the lowest bit
+    fn signed_number(&mut self) -> Result<i64, PatchError> {
+        let value = self.number()?;
+        let magnitude = (value >> 1) as i64;
+        Ok(if value & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "Patch is not in IPS, UPS or BPS format"),
+            PatchError::Truncated => write!(f, "Patch is truncated or corrupted"),
+            PatchError::OutOfRange => write!(f, "Patch holds a size or offset out of range"),
+            PatchError::SourceSizeMismatch { expected, actual } => {
+                write!(f, "Patch expects a ROM of {} bytes but it is {} bytes long", expected, actual)
+            }
+            PatchError::SourceChecksumMismatch { expected, actual } => {
+                write!(f, "Patch expects a ROM with CRC32 {:08X} but it has {:08X}", expected, actual)
+            }
+            PatchError::TargetChecksumMismatch { expected, actual } => {
+                write!(f, "Patched ROM should have CRC32 {:08X} but has {:08X}", expected, actual)
+            }
+            PatchError::PatchChecksumMismatch { expected, actual } => {
+                write!(f, "Patch declares CRC32 {:08X} but has {:08X}", expected, actual)
+            }
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+#[cfg(test)]
+mod patch_tests {
+    use super::*;
+
+    fn number(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte | 0x80);
+                return bytes;
+            }
+            bytes.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn should_apply_ips_records() {
+        let source = vec![0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend_from_slice(IPS_EOF);
+
+        let target = apply(&source, &patch).unwrap();
+        assert_eq!(target, vec![0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC]);
+
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+        assert_eq!(apply(&source, &patch).unwrap(), vec![0x00, 0xAA, 0xBB, 0x00]);
+    }
+
+    #[test]
+    fn should_apply_ups_hunks() {
+        let source = vec![0x10, 0x20, 0x30, 0x40];
+        let target = vec![0x10, 0x21, 0x30, 0x40, 0x00, 0x55];
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(number(4));
+        patch.extend(number(6));
+        patch.extend(number(1));
+        patch.extend_from_slice(&[0x01, 0x00]);
+        patch.extend(number(2));
+        patch.extend_from_slice(&[0x55, 0x00]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch), Ok(target));
+        assert!(matches!(apply(&[0x00; 4], &patch), Err(PatchError::SourceChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn should_apply_bps_actions() {
+        let source = b"ABCDEF".to_vec();
+        let target = b"ABCxyEFxyEFx".to_vec();
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(source.len() as u64));
+        patch.extend(number(target.len() as u64));
+        patch.extend(number(0));
+        patch.extend(number(((3 - 1) << 2) | SOURCE_READ));
+        patch.extend(number(((2 - 1) << 2) | TARGET_READ));
+        patch.extend_from_slice(b"xy");
+        patch.extend(number(((2 - 1) << 2) | SOURCE_COPY));
+        patch.extend(number(4 << 1));
+        patch.extend(number(((5 - 1) << 2) | TARGET_COPY));
+        patch.extend(number(3 << 1));
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch), Ok(target));
+    }
+
+    #[test]
+    fn should_reject_corrupted_patches() {
+        let source = vec![0u8; 4];
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(number(4));
+        patch.extend(number(4));
+        let mut patch = with_footer(patch, &source, &source);
+        patch[5] ^= 0xFF;
+
+        assert!(matches!(apply(&source, &patch), Err(PatchError::PatchChecksumMismatch { .. })));
+        assert_eq!(apply(&source, b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+    }
+
+    #[test]
+    fn should_reject_out_of_range_numbers() {
+        let source = vec![0u8; 4];
+
+        // Ten continuation bytes push a UPS number past 64 bits
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x7F; 10]);
+        patch.push(0xFF);
+        let patch = with_footer(patch, &source, &source);
+        assert_eq!(apply(&source, &patch), Err(PatchError::OutOfRange));
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(number(4));
+        patch.extend(number(u64::MAX >> 8));
+        let patch = with_footer(patch, &source, &source);
+        assert_eq!(apply(&source, &patch), Err(PatchError::OutOfRange));
+
+        // A BPS copy from before the start of the source
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(4));
+        patch.extend(number(4));
+        patch.extend(number(0));
+        patch.extend(number(((4 - 1) << 2) | SOURCE_COPY));
+        patch.extend(number((1 << 1) | 1));
+        let patch = with_footer(patch, &source, &source);
+        assert_eq!(apply(&source, &patch), Err(PatchError::OutOfRange));
+    }
+}
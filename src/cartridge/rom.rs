@@ -1,10 +1,7 @@
-use crate::cartridge::cartridge::Cartridge;
-use core::ops;
-use std::ops::{Index, Range, RangeInclusive};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use log::trace;
 use crate::memory::Address;
 
-const MEMORY_RANGE: RangeInclusive<Address> = 0x0000..=0x7FFF;
-
 pub struct RomOnly {
     data: Vec<u8>,
 }
@@ -15,47 +12,24 @@ impl RomOnly {
     }
 }
 
-impl Cartridge for RomOnly {}
-
-impl ops::Index<Address> for RomOnly {
-    type Output = u8;
-
-    fn index(&self, index: Address) -> &Self::Output {
-        if MEMORY_RANGE.contains(&index) {
-            &self.data
-                .get(index as usize)
-                .expect("Error fetching data")
-        } else {
-            panic!("Illegal ROM read access at address {:#X}", index)
+impl Cartridge for RomOnly {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            _ => 0xFF,
         }
     }
-}
-
-impl ops::Index<Range<Address>> for RomOnly {
-    type Output = [u8];
 
-    fn index(&self, index: Range<Address>) -> &Self::Output {
-        if MEMORY_RANGE.contains(&index.start) && MEMORY_RANGE.contains(&index.end) {
-            let range = index.start as usize .. index.end as usize;
-            &self.data
-                .get(range)
-                .expect("Error fetching data")
-        } else {
-            panic!("Illegal ROM read access at address {:#?}", index)
-        }
+    // Nothing listens to the bus, some games still write to ROM by mistake
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("Ignoring write {:#X} to ROM at {:#X}", data, address);
     }
-}
-
-impl ops::IndexMut<Address> for RomOnly {
 
-    fn index_mut(&mut self, index: Address) -> &mut Self::Output {
-        panic!("Illegal ROM write access at address {:#X}", index)
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 0, romx: 1, ram: None }
     }
-}
 
-impl ops::IndexMut<Range<u16>> for RomOnly {
-
-    fn index_mut(&mut self, index: Range<u16>) -> &mut Self::Output {
-        panic!("Illegal ROM write access at address {:#?}", index)
+    fn rom(&self) -> &[u8] {
+        &self.data
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,379 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cartridge::clock::Clock;
+use crate::cartridge::huc3::{self, Huc3Footer};
+use crate::cartridge::tama5::{self, Tama5Footer};
+
+// BGB and VBA-M append the clock after the RAM: five live registers and five latched ones
+// as 32 bit words, then a UNIX timestamp which older VBA versions store in 32 bits only
+pub const FOOTER_SIZE: usize = 48;
+pub const LEGACY_FOOTER_SIZE: usize = 44;
+
+// Battery RAM always comes in multiples of 32 bytes, the size of the TAMA5 one. Whatever is
+// left is the footer
+const RAM_GRANULARITY: usize = 32;
+
+// MBC3 registers, selected by writing 0x08-0x0C into 0x4000-0x5FFF
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAY_LOW: usize = 3;
+const DAY_HIGH: usize = 4;
+
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+const DAYS: u64 = 512;
+
+// Each clock has its own layout, told apart by size
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockFooter {
+    Mbc3(RtcFooter),
+    Huc3(Huc3Footer),
+    Tama5(Tama5Footer),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RtcFooter {
+    pub registers: [u32; 5],
+    pub latched: [u32; 5],
+    pub timestamp: u64,
+}
+
+impl RtcFooter {
+    pub fn parse(bytes: &[u8]) -> Option<RtcFooter> {
+        if bytes.len() != FOOTER_SIZE && bytes.len() != LEGACY_FOOTER_SIZE {
+            return None;
+        }
+
+        let word = |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        let mut footer = RtcFooter {
+            registers: [0; 5],
+            latched: [0; 5],
+            timestamp: match bytes.len() {
+                FOOTER_SIZE => u64::from_le_bytes(bytes[40..48].try_into().unwrap()),
+                _ => word(10) as u64,
+            },
+        };
+        for register in 0..5 {
+            footer.registers[register] = word(register);
+            footer.latched[register] = word(register + 5);
+        }
+        Some(footer)
+    }
+
+    pub fn to_bytes(self, legacy: bool) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.registers.iter()
+            .chain(&self.latched)
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect();
+
+        if legacy {
+            bytes.extend_from_slice(&(self.timestamp as u32).to_le_bytes());
+        } else {
+            bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+impl RtcFooter {
+    // Halt and day carry flags are left clear, the day counter wraps past 511
+    fn from_seconds(seconds: u64, timestamp: u64) -> RtcFooter {
+        let days = seconds / 86400 % DAYS;
+        let registers = [
+            (seconds % 60) as u32,
+            (seconds / 60 % 60) as u32,
+            (seconds / 3600 % 24) as u32,
+            (days & 0xFF) as u32,
+            (days >> 8) as u32,
+        ];
+        RtcFooter { registers, latched: registers, timestamp }
+    }
+
+    fn seconds(&self) -> u64 {
+        let [seconds, minutes, hours, day_low, day_high] = self.registers;
+        let days = (day_low as u64 & 0xFF) | (day_high as u64 & 0x01) << 8;
+        seconds as u64 % 60 + minutes as u64 % 60 * 60 + hours as u64 % 24 * 3600 + days * 86400
+    }
+}
+
+impl ClockFooter {
+    pub fn parse(bytes: &[u8]) -> Option<ClockFooter> {
+        RtcFooter::parse(bytes).map(ClockFooter::Mbc3)
+            .or_else(|| Huc3Footer::parse(bytes).map(ClockFooter::Huc3))
+            .or_else(|| Tama5Footer::parse(bytes).map(ClockFooter::Tama5))
+    }
+
+    // MBC3 clocks go in the BGB layout with a 64 bit timestamp
+    pub fn to_bytes(self) -> Vec<u8> {
+        match self {
+            ClockFooter::Mbc3(footer) => footer.to_bytes(false),
+            ClockFooter::Huc3(footer) => footer.to_bytes(),
+            ClockFooter::Tama5(footer) => footer.to_bytes(),
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            ClockFooter::Mbc3(footer) => footer.timestamp,
+            ClockFooter::Huc3(footer) => footer.timestamp,
+            ClockFooter::Tama5(footer) => footer.timestamp,
+        }
+    }
+
+    // Saves moved between MBC3 and HuC3 keep the time on the clock, the TAMA5 calendar has
+    // no counterpart in either
+    pub fn mbc3(self) -> Option<RtcFooter> {
+        match self {
+            ClockFooter::Mbc3(footer) => Some(footer),
+            ClockFooter::Huc3(footer) => Some(RtcFooter::from_seconds(footer.seconds(), footer.timestamp)),
+            ClockFooter::Tama5(_) => None,
+        }
+    }
+
+    pub fn huc3(self) -> Option<Huc3Footer> {
+        match self {
+            ClockFooter::Mbc3(footer) => Some(Huc3Footer::from_seconds(footer.seconds(), footer.timestamp)),
+            ClockFooter::Huc3(footer) => Some(footer),
+            ClockFooter::Tama5(_) => None,
+        }
+    }
+}
+
+// Size of the footer at the end of a save file, if any. Each size leaves its own remainder
+pub fn footer_size(save_size: usize) -> usize {
+    [FOOTER_SIZE, LEGACY_FOOTER_SIZE, huc3::FOOTER_SIZE, tama5::FOOTER_SIZE]
+        .iter()
+        .copied()
+        .find(|&size| save_size >= size && (save_size - size).is_multiple_of(RAM_GRANULARITY))
+        .unwrap_or(0)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Footers of host clocks are in unshifted host time, other emulators and the next session
+// may not share the offset. Frozen and cycle clocks keep their own time, so that a replay
+// reads the same registers on every run
+pub fn stamp(source: &dyn Clock) -> u64 {
+    if source.follows_host() {
+        now()
+    } else {
+        source.now()
+    }
+}
+
+// Time the clock kept running since the footer was written
+pub fn elapsed_since(source: &dyn Clock, timestamp: u64) -> u64 {
+    stamp(source).saturating_sub(timestamp)
+}
+
+// https://gbdev.io/pandocs/MBC3.html#the-clock-counter-registers
+// Registers hold the time as of the last update, the clock catches up with its time source
+// whenever it is accessed
+pub struct RealTimeClock {
+    registers: [u8; 5],
+    latched: [u8; 5],
+    last_update: u64,
+    latch_armed: bool,
+    source: Box<dyn Clock>,
+}
+
+impl RealTimeClock {
+    pub fn new(source: Box<dyn Clock>) -> RealTimeClock {
+        RealTimeClock {
+            registers: [0; 5],
+            latched: [0; 5],
+            last_update: source.now(),
+            latch_armed: false,
+            source,
+        }
+    }
+
+    // Time elapsed under the previous source is kept, the new one counts from now on
+    pub fn set_source(&mut self, source: Box<dyn Clock>) {
+        self.update();
+        self.source = source;
+        self.last_update = self.source.now();
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.source.tick(cycles);
+    }
+
+    fn update(&mut self) {
+        self.registers = self.current();
+        self.last_update = self.source.now();
+    }
+
+    // The registers as of now, without moving the last update
+    fn current(&self) -> [u8; 5] {
+        let mut registers = self.registers;
+        advance(&mut registers, self.source.now().saturating_sub(self.last_update));
+        registers
+    }
+
+    // Writing 0 then 1 copies the running time into the registers the game reads
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_armed && data == 0x01 {
+            self.update();
+            self.latched = self.registers;
+        }
+        self.latch_armed = data == 0x00;
+    }
+
+    pub fn latched(&self, register: usize) -> u8 {
+        self.latched[register]
+    }
+
+    pub fn write(&mut self, register: usize, data: u8) {
+        self.update();
+        self.registers[register] = data & REGISTER_MASKS[register];
+    }
+
+    pub fn footer(&self) -> RtcFooter {
+        RtcFooter {
+            registers: self.current().map(u32::from),
+            latched: self.latched.map(u32::from),
+            timestamp: stamp(self.source.as_ref()),
+        }
+    }
+
+    // The clock kept running while the game was off, unless it was halted
+    pub fn load_footer(&mut self, footer: &RtcFooter) {
+        for (register, mask) in REGISTER_MASKS.iter().enumerate() {
+            self.registers[register] = footer.registers[register] as u8 & mask;
+            self.latched[register] = footer.latched[register] as u8 & mask;
+        }
+        advance(&mut self.registers, elapsed_since(self.source.as_ref(), footer.timestamp));
+        self.last_update = self.source.now();
+    }
+}
+
+// Nothing moves while halted
+fn advance(registers: &mut [u8; 5], seconds: u64) {
+    if registers[DAY_HIGH] & HALT != 0 {
+        return;
+    }
+    let days = registers[DAY_LOW] as u64 | ((registers[DAY_HIGH] as u64 & 0x01) << 8);
+    let time = registers[SECONDS] as u64
+        + registers[MINUTES] as u64 * 60
+        + registers[HOURS] as u64 * 3600
+        + days * 86400
+        + seconds;
+
+    registers[SECONDS] = (time % 60) as u8;
+    registers[MINUTES] = (time / 60 % 60) as u8;
+    registers[HOURS] = (time / 3600 % 24) as u8;
+
+    let days = time / 86400;
+    let carry = if days >= DAYS { DAY_CARRY } else { registers[DAY_HIGH] & DAY_CARRY };
+    registers[DAY_LOW] = (days % DAYS) as u8;
+    registers[DAY_HIGH] = carry | (registers[DAY_HIGH] & HALT) | ((days % DAYS) >> 8) as u8;
+}
+
+#[cfg(test)]
+mod rtc_tests {
+    use super::*;
+    use crate::cartridge::clock::{CycleClock, FrozenClock, SystemClock, CYCLES_PER_SECOND};
+
+    #[test]
+    fn should_round_trip_both_footer_layouts() {
+        let footer = RtcFooter { registers: [1, 2, 3, 4, 0x41], latched: [5, 6, 7, 8, 0], timestamp: 1_600_000_000 };
+
+        let bytes = footer.to_bytes(false);
+        assert_eq!(bytes.len(), FOOTER_SIZE);
+        assert_eq!(RtcFooter::parse(&bytes), Some(footer));
+
+        let bytes = footer.to_bytes(true);
+        assert_eq!(bytes.len(), LEGACY_FOOTER_SIZE);
+        assert_eq!(RtcFooter::parse(&bytes), Some(footer));
+    }
+
+    #[test]
+    fn should_find_footer_after_ram() {
+        assert_eq!(footer_size(0x2000), 0);
+        assert_eq!(footer_size(0x2000 + FOOTER_SIZE), FOOTER_SIZE);
+        assert_eq!(footer_size(0x8000 + LEGACY_FOOTER_SIZE), LEGACY_FOOTER_SIZE);
+        assert_eq!(footer_size(FOOTER_SIZE), FOOTER_SIZE);
+        assert_eq!(footer_size(0x800 + huc3::FOOTER_SIZE), huc3::FOOTER_SIZE);
+        assert_eq!(footer_size(32 + tama5::FOOTER_SIZE), tama5::FOOTER_SIZE);
+    }
+
+    #[test]
+    fn should_keep_time_between_mbc3_and_huc3_layouts() {
+        let footer = RtcFooter { registers: [0, 30, 5, 0x2C, 0x01], latched: [0; 5], timestamp: 1_600_000_000 };
+
+        let huc3 = ClockFooter::Mbc3(footer).huc3().unwrap();
+        assert_eq!((huc3.minutes, huc3.days), (330, 300));
+
+        let mbc3 = ClockFooter::Huc3(huc3).mbc3().unwrap();
+        assert_eq!(mbc3.registers, footer.registers);
+        assert_eq!(mbc3.timestamp, footer.timestamp);
+    }
+
+    #[test]
+    fn should_carry_over_day_counter() {
+        let mut registers = [50, 59, 23, 0xFF, 0x01];
+        advance(&mut registers, 20);
+
+        assert_eq!(registers, [10, 0, 0, 0, DAY_CARRY]);
+    }
+
+    #[test]
+    fn should_stand_still_while_halted() {
+        let mut clock = RealTimeClock::new(Box::new(FrozenClock { time: 1_600_003_600 }));
+        let footer = RtcFooter { registers: [30, 0, 0, 0, HALT as u32], latched: [0; 5], timestamp: now() - 3600 };
+        clock.load_footer(&footer);
+
+        assert_eq!(clock.registers[SECONDS], 30);
+        assert_eq!(clock.registers[HOURS], 0);
+    }
+
+    #[test]
+    fn should_save_host_time_for_host_clocks() {
+        let mut system = RealTimeClock::new(Box::new(SystemClock::default()));
+        system.write(HOURS, 5);
+        let footer = system.footer();
+        assert!(footer.timestamp + 60 >= now());
+
+        // An hour later on the host, the next session runs three days ahead
+        let footer = RtcFooter { timestamp: now() - 3600, ..footer };
+        let mut ahead = RealTimeClock::new(Box::new(SystemClock { offset: 3 * 86400 }));
+        ahead.load_footer(&footer);
+        assert_eq!(ahead.current()[HOURS], 6);
+        assert_eq!(ahead.current()[DAY_LOW], 0);
+        assert!(ahead.footer().timestamp <= now());
+    }
+
+    #[test]
+    fn should_not_catch_up_with_host_under_frozen_clock() {
+        let registers = [30, 20, 10, 5, 0];
+        let footer = RtcFooter { registers, latched: registers, timestamp: 1_600_000_000 };
+
+        let mut clock = RealTimeClock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        clock.load_footer(&footer);
+        assert_eq!(clock.current().map(u32::from), registers);
+        assert_eq!(clock.footer(), footer);
+
+        // A footer written by a host clock an hour ago
+        let mut clock = RealTimeClock::new(Box::new(FrozenClock { time: 1_600_000_000 }));
+        clock.load_footer(&RtcFooter { timestamp: now() - 3600, ..footer });
+        assert_eq!(clock.current().map(u32::from), registers);
+    }
+
+    #[test]
+    fn should_follow_emulated_cycles() {
+        let mut clock = RealTimeClock::new(Box::new(CycleClock::new(0)));
+        for _ in 0..90 {
+            clock.tick(CYCLES_PER_SECOND);
+        }
+        clock.write_latch(0x00);
+        clock.write_latch(0x01);
+
+        assert_eq!(clock.latched(SECONDS), 30);
+        assert_eq!(clock.latched(MINUTES), 1);
+    }
+}
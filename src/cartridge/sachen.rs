@@ -0,0 +1,137 @@
+use std::cell::Cell;
+use std::ops::Range;
+use log::{debug, trace};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::memory::Address;
+
+// Sachen MMC1/MMC2 mappers. Until the boot ROM is done with the header, address lines
+// A0/A6 and A1/A4 are swapped in 0x0100-0x01FF so it finds a Nintendo logo hidden among
+// the real header bytes
+const ROM_BANK_SIZE: usize = 0x4000;
+const SCRAMBLED_AREA: Range<Address> = 0x0100..0x0200;
+const LOGO_AREA: Range<Address> = 0x0104..0x0134;
+
+// The boot ROM goes over the logo twice, drawing it and then comparing it
+const UNLOCK_READS: u32 = 2 * 0x30;
+
+pub fn scramble(address: Address) -> Address {
+    (address & 0xFFAC)
+        | (address & 0x40) >> 6
+        | (address & 0x10) >> 3
+        | (address & 0x02) << 3
+        | (address & 0x01) << 6
+}
+
+pub struct SachenCartridge {
+    data: Vec<u8>,
+    base_bank: u8,
+    bank_mask: u8,
+    current_rom_bank: u8,
+    logo_reads: Cell<u32>,
+}
+
+impl SachenCartridge {
+    pub fn new(data: Vec<u8>) -> SachenCartridge {
+        SachenCartridge {
+            data,
+            base_bank: 0xFF,
+            bank_mask: 0x00,
+            current_rom_bank: 1,
+            logo_reads: Cell::new(0),
+        }
+    }
+
+    fn locked(&self) -> bool {
+        self.logo_reads.get() < UNLOCK_READS
+    }
+
+    // Bits set in the mask come from the base bank, which lets multicarts confine each game
+    fn bank(&self, bank: u8) -> usize {
+        ((bank & !self.bank_mask) | (self.base_bank & self.bank_mask)) as usize
+    }
+
+    fn read_rom(&self, bank: usize, address: Address) -> u8 {
+        self.data[(bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))) % self.data.len()]
+    }
+}
+
+impl Cartridge for SachenCartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            address if SCRAMBLED_AREA.contains(&address) && self.locked() => {
+                if LOGO_AREA.contains(&address) {
+                    self.logo_reads.set(self.logo_reads.get() + 1);
+                    if !self.locked() {
+                        debug!("Sachen header unlocked");
+                    }
+                }
+                self.read_rom(self.bank(0), scramble(address))
+            }
+            0x0000..=0x3FFF => self.read_rom(self.bank(0), address),
+            0x4000..=0x7FFF => self.read_rom(self.bank(self.current_rom_bank), address),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("Sachen write {:#X} at {:#X}", data, address);
+
+        // Base and mask can no longer change once a base outside the menu has been chosen
+        let configurable = self.base_bank & 0x30 == 0x30;
+
+        match address {
+            0x0000..=0x1FFF if configurable => self.base_bank = data,
+            0x2000..=0x3FFF => self.current_rom_bank = if data == 0 { 1 } else { data },
+            0x4000..=0x5FFF if configurable => self.bank_mask = data,
+            _ => {}
+        }
+    }
+
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: self.bank(0), romx: self.bank(self.current_rom_bank), ram: None }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod sachen_tests {
+    use super::*;
+
+    #[test]
+    fn should_swap_address_lines() {
+        assert_eq!(scramble(0x0101), 0x0140);
+        assert_eq!(scramble(0x0102), 0x0110);
+        assert_eq!(scramble(scramble(0x0133)), 0x0133);
+    }
+
+    #[test]
+    fn should_unscramble_after_boot_rom_checks() {
+        let mut blob = vec![0; 0x8000];
+        blob[0x0140] = 0xCE;
+        blob[0x0104] = 0x11;
+        blob[0x0101] = 0x22;
+        let cartridge = SachenCartridge::new(blob);
+
+        assert_eq!(cartridge.read(0x0101), 0xCE);
+        for _ in 0..UNLOCK_READS {
+            cartridge.read(0x0104);
+        }
+        assert_eq!(cartridge.read(0x0101), 0x22);
+    }
+
+    #[test]
+    fn should_lock_base_bank() {
+        let mut cartridge = SachenCartridge::new(vec![0; 16 * ROM_BANK_SIZE]);
+
+        cartridge.write(0x4000, 0x0C);
+        cartridge.write(0x0000, 0x04);
+        cartridge.write(0x2000, 0x01);
+        assert_eq!(cartridge.bank(cartridge.current_rom_bank), 0x05);
+
+        cartridge.write(0x0000, 0x08);
+        assert_eq!(cartridge.base_bank, 0x04);
+    }
+}
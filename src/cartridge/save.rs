@@ -0,0 +1,199 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::{info, warn};
+use crate::cartridge::cartridge::Cartridge;
+use crate::cartridge::rtc::{self, ClockFooter, RtcFooter};
+use crate::memory::MemorySpace;
+
+const KB: usize = 1024;
+
+// Clock footer written by a conversion
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FooterFormat {
+    Keep,
+    None,
+    Modern,
+    Legacy,
+    Huc3,
+}
+
+// Battery saves live next to the ROM, sharing its name
+pub fn save_path(rom: &Path) -> PathBuf {
+    rom.with_extension("sav")
+}
+
+// Saves from other emulators may carry a clock footer, and flash carts pad the RAM
+// up to 32 or 64KB. The cartridge takes as much RAM as it has
+pub fn load(cartridge: &mut dyn Cartridge, path: &Path) -> io::Result<()> {
+    let has_battery = cartridge.save_ram().is_some() || cartridge.save_rtc().is_some();
+    if !has_battery || !path.exists() {
+        return Ok(());
+    }
+
+    let data = fs::read(path)?;
+    let (ram, footer) = split(&data);
+    cartridge.load_ram(ram);
+    info!("Loaded {} bytes of save data from {}", ram.len(), path.display());
+
+    if let Some(footer) = footer {
+        cartridge.load_rtc(&footer);
+        info!("Loaded clock state saved at UNIX time {}", footer.timestamp());
+    }
+    Ok(())
+}
+
+pub fn store(cartridge: &dyn Cartridge, path: &Path) -> io::Result<()> {
+    let mut data = cartridge.save_ram().map(<[u8]>::to_vec).unwrap_or_default();
+    if let Some(footer) = cartridge.save_rtc() {
+        data.extend(footer.to_bytes());
+    }
+
+    if !data.is_empty() {
+        fs::write(path, &data)?;
+        info!("Stored {} bytes of save data into {}", data.len(), path.display());
+    }
+    Ok(())
+}
+
+// Pulls the cartridge out of a running console, its battery save is flushed on the way out
+pub fn eject(memory: &mut MemorySpace, path: &Path) -> io::Result<Box<dyn Cartridge>> {
+    let cartridge = memory.eject();
+    store(cartridge.as_ref(), path)?;
+    Ok(cartridge)
+}
+
+// Plugs a cartridge into a running console with its battery save loaded, returning what
+// was in the slot
+pub fn insert(memory: &mut MemorySpace, mut cartridge: Box<dyn Cartridge>, path: &Path) -> io::Result<Box<dyn Cartridge>> {
+    load(cartridge.as_mut(), path)?;
+    let previous = memory.insert(cartridge);
+    if previous.is_inserted() {
+        warn!("Cartridge swapped without being ejected, its save was not flushed");
+    }
+    Ok(previous)
+}
+
+pub fn split(data: &[u8]) -> (&[u8], Option<ClockFooter>) {
+    let (ram, footer) = data.split_at(data.len() - rtc::footer_size(data.len()));
+    (ram, ClockFooter::parse(footer))
+}
+
+// Resizes the RAM, pads with 0xFF like erased flash, and rewrites the clock footer
+pub fn convert(data: &[u8], footer_format: FooterFormat, ram_size: Option<usize>) -> Vec<u8> {
+    let (ram, footer) = split(data);
+    let mut converted = ram.to_vec();
+
+    if let Some(size) = ram_size {
+        if ram[size.min(ram.len())..].iter().any(|&byte| byte != 0x00 && byte != 0xFF) {
+            warn!("Trimming the save to {} bytes drops data", size);
+        }
+        converted.resize(size, 0xFF);
+    }
+
+    // A save without a clock gets one starting from zero, games then ask for the time again
+    let footer = footer.unwrap_or(ClockFooter::Mbc3(RtcFooter { registers: [0; 5], latched: [0; 5], timestamp: rtc::now() }));
+    let rewritten = match footer_format {
+        FooterFormat::Keep => Some(data[ram.len()..].to_vec()),
+        FooterFormat::None => Some(Vec::new()),
+        FooterFormat::Modern => footer.mbc3().map(|footer| footer.to_bytes(false)),
+        FooterFormat::Legacy => footer.mbc3().map(|footer| footer.to_bytes(true)),
+        FooterFormat::Huc3 => footer.huc3().map(|footer| footer.to_bytes()),
+    };
+    match rewritten {
+        Some(bytes) => converted.extend(bytes),
+        None => {
+            warn!("A TAMA5 clock footer has no {:?} layout, it is kept as is", footer_format);
+            converted.extend_from_slice(&data[ram.len()..]);
+        }
+    }
+    converted
+}
+
+impl FromStr for FooterFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "keep" => Ok(FooterFormat::Keep),
+            "none" => Ok(FooterFormat::None),
+            "48" => Ok(FooterFormat::Modern),
+            "44" => Ok(FooterFormat::Legacy),
+            "huc3" => Ok(FooterFormat::Huc3),
+            _ => Err(format!("Unknown footer {}, expected keep, none, 48, 44 or huc3", format)),
+        }
+    }
+}
+
+// Sizes are given in KB on the command line, as flash carts document them
+pub fn parse_kilobytes(size: &str) -> Result<usize, String> {
+    size.trim_end_matches(['K', 'k'])
+        .parse::<usize>()
+        .map(|size| size * KB)
+        .map_err(|e| format!("Invalid size {}: {}", size, e))
+}
+
+#[cfg(test)]
+mod save_tests {
+    use super::*;
+
+    #[test]
+    fn should_split_footer_from_ram() {
+        let footer = RtcFooter { registers: [1, 2, 3, 4, 0], latched: [0; 5], timestamp: 42 };
+        let mut data = vec![0x11; 0x2000];
+        data.extend(footer.to_bytes(true));
+
+        let (ram, parsed) = split(&data);
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(parsed, Some(ClockFooter::Mbc3(footer)));
+        assert_eq!(split(&data[..0x2000]), (&data[..0x2000], None));
+    }
+
+    #[test]
+    fn should_convert_between_layouts() {
+        let footer = RtcFooter { registers: [1, 2, 3, 4, 0], latched: [0; 5], timestamp: 42 };
+        let mut data = vec![0x11; 0x2000];
+        data.extend(footer.to_bytes(false));
+
+        let padded = convert(&data, FooterFormat::Legacy, Some(32 * KB));
+        assert_eq!(padded.len(), 32 * KB + rtc::LEGACY_FOOTER_SIZE);
+        assert_eq!(padded[0x2000], 0xFF);
+
+        let raw = convert(&padded, FooterFormat::None, Some(8 * KB));
+        assert_eq!(raw, vec![0x11; 0x2000]);
+
+        let restored = convert(&padded, FooterFormat::Modern, Some(8 * KB));
+        assert_eq!(restored, data);
+
+        let huc3 = convert(&data, FooterFormat::Huc3, None);
+        assert_eq!(huc3.len(), 0x2000 + crate::cartridge::huc3::FOOTER_SIZE);
+        // Minutes, hours and days come back, seconds went into the timestamp
+        assert_eq!(convert(&huc3, FooterFormat::Modern, None)[0x2004..0x2014], data[0x2004..0x2014]);
+    }
+
+    #[test]
+    fn should_flush_save_when_ejecting() {
+        let path = std::env::temp_dir().join(format!("rustboy-eject-{}.sav", std::process::id()));
+        let mut blob = vec![0; 0x8000];
+        blob[0x0147] = 0x13;
+        blob[0x0149] = 0x02;
+
+        let cartridge = crate::cartridge::cartridge::decode_cartridge_as(blob.clone(), crate::cartridge::mapper::Mapper::Mbc3);
+        let mut memory = MemorySpace::new(cartridge);
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA000, 0x42);
+
+        eject(&mut memory, &path).unwrap();
+        assert!(!memory.cartridge().is_inserted());
+        assert_eq!(memory.read(0xA000), 0xFF);
+        memory.write(0xA000, 0x24);
+
+        let cartridge = crate::cartridge::cartridge::decode_cartridge_as(blob, crate::cartridge::mapper::Mapper::Mbc3);
+        insert(&mut memory, cartridge, &path).unwrap();
+        memory.write(0x0000, 0x0A);
+        assert_eq!(memory.read(0xA000), 0x42);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+use std::convert::TryInto;
+use log::{debug, trace, warn};
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::cartridge::clock::{Clock, SystemClock};
+use crate::cartridge::rtc::{self, ClockFooter};
+use crate::memory::Address;
+
+// https://gbdev.io/pandocs/TAMA5.html
+// The chip is driven one nibble at a time: 0xA001 selects a register, 0xA000 writes
+// its low nibble or reads it back with the upper nibble set
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_SIZE: usize = 32;
+
+const ROM_BANK_LOW: usize = 0x0;
+const ROM_BANK_HIGH: usize = 0x1;
+const VALUE_LOW: usize = 0x4;
+const VALUE_HIGH: usize = 0x5;
+const COMMAND: usize = 0x6;
+const ADDRESS_LOW: usize = 0x7;
+const STATUS: usize = 0xA;
+const RESULT_LOW: usize = 0xC;
+const RESULT_HIGH: usize = 0xD;
+
+// Commands held in register 6 bits 1-3, executed when register 7 is written
+const WRITE_RAM: u8 = 0x0;
+const READ_RAM: u8 = 0x1;
+const WRITE_RTC: u8 = 0x2;
+const READ_RTC: u8 = 0x3;
+
+const READY: u8 = 0x1;
+
+pub struct Tama5Cartridge {
+    data: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    registers: [u8; 16],
+    selected: usize,
+    output: u8,
+    rtc: Tc8521,
+}
+
+impl Tama5Cartridge {
+    pub fn new(blob: Vec<u8>) -> Tama5Cartridge {
+        let mut registers = [0; 16];
+        registers[STATUS] = READY;
+
+        Tama5Cartridge {
+            data: blob,
+            ram: [0; RAM_SIZE],
+            registers,
+            selected: 0,
+            output: 0xF0,
+            rtc: Tc8521::new(Box::new(SystemClock::default())),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.registers[ROM_BANK_HIGH] as usize & 0x01) << 4) | self.registers[ROM_BANK_LOW] as usize
+    }
+
+    fn execute(&mut self) {
+        let address = ((self.registers[COMMAND] as usize & 0x01) << 4) | self.registers[ADDRESS_LOW] as usize;
+        let value = (self.registers[VALUE_HIGH] << 4) | self.registers[VALUE_LOW];
+
+        match self.registers[COMMAND] >> 1 {
+            WRITE_RAM => self.ram[address] = value,
+            READ_RAM => {
+                self.registers[RESULT_LOW] = self.ram[address] & 0x0F;
+                self.registers[RESULT_HIGH] = self.ram[address] >> 4;
+            }
+            WRITE_RTC => self.rtc.write(address & 0x0F, self.registers[VALUE_LOW]),
+            READ_RTC => {
+                self.registers[RESULT_LOW] = self.rtc.read(address & 0x0F);
+                self.registers[RESULT_HIGH] = 0;
+            }
+            command => debug!("Unknown TAMA5 command {:#X}", command),
+        }
+    }
+}
+
+impl Cartridge for Tama5Cartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.data.get(address as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.rom_bank() * ROM_BANK_SIZE + (address as usize - ROM_BANK_SIZE);
+                self.data[offset % self.data.len()]
+            }
+            0xA000 => self.output,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("TAMA5 write {:#X} at {:#X}", data, address);
+
+        match address {
+            0xA001 => self.selected = (data & 0x0F) as usize,
+            0xA000 => {
+                self.registers[self.selected] = data & 0x0F;
+                if self.selected == ADDRESS_LOW {
+                    self.execute();
+                }
+            }
+            _ => {}
+        }
+
+        self.output = match self.selected {
+            STATUS | RESULT_LOW | RESULT_HIGH => 0xF0 | self.registers[self.selected],
+            _ => 0xFF,
+        };
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.source.tick(cycles);
+    }
+
+    // RAM is only reachable through commands, it is never mapped
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 0, romx: self.rom_bank(), ram: None }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let length = data.len().min(RAM_SIZE);
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    fn save_rtc(&self) -> Option<ClockFooter> {
+        Some(ClockFooter::Tama5(self.rtc.footer()))
+    }
+
+    fn load_rtc(&mut self, footer: &ClockFooter) {
+        match footer {
+            ClockFooter::Tama5(footer) => self.rtc.load_footer(footer),
+            _ => warn!("Ignoring a clock footer saved by another mapper"),
+        }
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.rtc.set_source(clock);
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// Not shared with other emulators: seconds, minutes, hours, weekday, day, month and year
+// bytes, then a 64 bit UNIX timestamp
+pub const FOOTER_SIZE: usize = 15;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tama5Footer {
+    pub calendar: [u8; 7],
+    pub timestamp: u64,
+}
+
+impl Tama5Footer {
+    pub fn parse(bytes: &[u8]) -> Option<Tama5Footer> {
+        if bytes.len() != FOOTER_SIZE {
+            return None;
+        }
+        Some(Tama5Footer {
+            calendar: bytes[..7].try_into().unwrap(),
+            timestamp: u64::from_le_bytes(bytes[7..].try_into().unwrap()),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.calendar.to_vec();
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Calendar {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    weekday: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+impl Calendar {
+    fn advance(&mut self, seconds: u64) {
+        let time = self.second as u64 + self.minute as u64 * 60 + self.hour as u64 * 3600 + seconds;
+        self.second = (time % 60) as u8;
+        self.minute = (time / 60 % 60) as u8;
+        self.hour = (time / 3600 % 24) as u8;
+
+        for _ in 0..time / 86400 {
+            self.next_day();
+        }
+    }
+
+    fn next_day(&mut self) {
+        self.weekday = (self.weekday + 1) % 7;
+        self.day += 1;
+
+        if self.day > self.days_in_month() {
+            self.day = 1;
+            self.month += 1;
+        }
+        if self.month > 12 {
+            self.month = 1;
+            self.year = (self.year + 1) % 100;
+        }
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+// Toshiba TC8521 real time clock, exposed as one BCD digit per register
+struct Tc8521 {
+    time: Calendar,
+    last_update: u64,
+    source: Box<dyn Clock>,
+}
+
+impl Tc8521 {
+    fn new(source: Box<dyn Clock>) -> Tc8521 {
+        Tc8521 {
+            time: Calendar { second: 0, minute: 0, hour: 0, weekday: 0, day: 1, month: 1, year: 0 },
+            last_update: source.now(),
+            source,
+        }
+    }
+
+    fn set_source(&mut self, source: Box<dyn Clock>) {
+        self.update();
+        self.source = source;
+        self.last_update = self.source.now();
+    }
+
+    fn update(&mut self) {
+        self.time = self.current();
+        self.last_update = self.source.now();
+    }
+
+    // The calendar as of now, without moving the last update
+    fn current(&self) -> Calendar {
+        let mut time = self.time;
+        time.advance(self.source.now().saturating_sub(self.last_update));
+        time
+    }
+
+    fn footer(&self) -> Tama5Footer {
+        let Calendar { second, minute, hour, weekday, day, month, year } = self.current();
+        Tama5Footer { calendar: [second, minute, hour, weekday, day, month, year], timestamp: rtc::stamp(self.source.as_ref()) }
+    }
+
+    // Out of range values from a damaged save are pulled back in, as register writes are
+    fn load_footer(&mut self, footer: &Tama5Footer) {
+        let [second, minute, hour, weekday, day, month, year] = footer.calendar;
+        self.time = Calendar {
+            second: second.min(59),
+            minute: minute.min(59),
+            hour: hour.min(23),
+            weekday: weekday % 7,
+            day: day.clamp(1, 31),
+            month: month.clamp(1, 12),
+            year: year % 100,
+        };
+        self.time.advance(rtc::elapsed_since(self.source.as_ref(), footer.timestamp));
+        self.last_update = self.source.now();
+    }
+
+    fn read(&mut self, register: usize) -> u8 {
+        self.update();
+        let time = &self.time;
+
+        match register {
+            0x0 => time.second % 10,
+            0x1 => time.second / 10,
+            0x2 => time.minute % 10,
+            0x3 => time.minute / 10,
+            0x4 => time.hour % 10,
+            0x5 => time.hour / 10,
+            0x6 => time.weekday,
+            0x7 => time.day % 10,
+            0x8 => time.day / 10,
+            0x9 => time.month % 10,
+            0xA => time.month / 10,
+            0xB => time.year % 10,
+            0xC => time.year / 10,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, register: usize, digit: u8) {
+        self.update();
+        let time = &mut self.time;
+
+        let set_units = |value: u8| value / 10 * 10 + digit % 10;
+        let set_tens = |value: u8| (digit % 10) * 10 + value % 10;
+
+        match register {
+            0x0 => time.second = set_units(time.second).min(59),
+            0x1 => time.second = set_tens(time.second).min(59),
+            0x2 => time.minute = set_units(time.minute).min(59),
+            0x3 => time.minute = set_tens(time.minute).min(59),
+            0x4 => time.hour = set_units(time.hour).min(23),
+            0x5 => time.hour = set_tens(time.hour).min(23),
+            0x6 => time.weekday = digit % 7,
+            0x7 => time.day = set_units(time.day).clamp(1, 31),
+            0x8 => time.day = set_tens(time.day).clamp(1, 31),
+            0x9 => time.month = set_units(time.month).clamp(1, 12),
+            0xA => time.month = set_tens(time.month).clamp(1, 12),
+            0xB => time.year = set_units(time.year),
+            0xC => time.year = set_tens(time.year),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tama5_tests {
+    use super::*;
+    use crate::cartridge::clock::FrozenClock;
+
+    fn write_register(cartridge: &mut Tama5Cartridge, register: u8, value: u8) {
+        cartridge.write(0xA001, register);
+        cartridge.write(0xA000, value);
+    }
+
+    fn read_register(cartridge: &mut Tama5Cartridge, register: u8) -> u8 {
+        cartridge.write(0xA001, register);
+        cartridge.read(0xA000)
+    }
+
+    #[test]
+    fn should_report_ready() {
+        let mut cartridge = Tama5Cartridge::new(vec![0; 0x8000]);
+        assert_eq!(read_register(&mut cartridge, STATUS as u8), 0xF1);
+    }
+
+    #[test]
+    fn should_write_and_read_ram_through_nibbles() {
+        let mut cartridge = Tama5Cartridge::new(vec![0; 0x8000]);
+
+        write_register(&mut cartridge, VALUE_LOW as u8, 0x5);
+        write_register(&mut cartridge, VALUE_HIGH as u8, 0xA);
+        write_register(&mut cartridge, COMMAND as u8, (WRITE_RAM << 1) | 0x01);
+        write_register(&mut cartridge, ADDRESS_LOW as u8, 0x3);
+        assert_eq!(cartridge.ram[0x13], 0xA5);
+
+        write_register(&mut cartridge, COMMAND as u8, (READ_RAM << 1) | 0x01);
+        write_register(&mut cartridge, ADDRESS_LOW as u8, 0x3);
+        assert_eq!(read_register(&mut cartridge, RESULT_LOW as u8), 0xF5);
+        assert_eq!(read_register(&mut cartridge, RESULT_HIGH as u8), 0xFA);
+    }
+
+    #[test]
+    fn should_switch_rom_banks() {
+        let mut blob = vec![0; 32 * ROM_BANK_SIZE];
+        blob[17 * ROM_BANK_SIZE] = 0x42;
+        let mut cartridge = Tama5Cartridge::new(blob);
+
+        write_register(&mut cartridge, ROM_BANK_LOW as u8, 0x1);
+        write_register(&mut cartridge, ROM_BANK_HIGH as u8, 0x1);
+        assert_eq!(cartridge.read(0x4000), 0x42);
+    }
+
+    #[test]
+    fn should_roll_calendar_over() {
+        let mut time = Calendar { second: 59, minute: 59, hour: 23, weekday: 0, day: 31, month: 12, year: 3 };
+
+        time.advance(1 + 59 * 86400);
+
+        assert_eq!((time.year, time.month, time.day), (4, 2, 29));
+        assert_eq!((time.hour, time.minute, time.second), (0, 0, 0));
+    }
+
+    #[test]
+    fn should_keep_calendar_in_save() {
+        let path = std::env::temp_dir().join(format!("rustboy-tama5-{}.sav", std::process::id()));
+        let mut cartridge = Tama5Cartridge::new(vec![0; 0x8000]);
+        cartridge.set_clock(Box::new(FrozenClock { time: 0 }));
+        cartridge.ram[0] = 0x42;
+        cartridge.rtc.time = Calendar { second: 10, minute: 20, hour: 5, weekday: 2, day: 14, month: 7, year: 97 };
+
+        crate::cartridge::save::store(&cartridge, &path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), (RAM_SIZE + FOOTER_SIZE) as u64);
+
+        let mut restored = Tama5Cartridge::new(vec![0; 0x8000]);
+        restored.set_clock(Box::new(FrozenClock { time: 0 }));
+        crate::cartridge::save::load(&mut restored, &path).unwrap();
+        assert_eq!(restored.ram[0], 0x42);
+        assert_eq!(restored.rtc.current(), cartridge.rtc.time);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
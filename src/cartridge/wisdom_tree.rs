@@ -0,0 +1,70 @@
+use log::trace;
+use crate::cartridge::cartridge::{Banks, Cartridge};
+use crate::memory::Address;
+
+// Wisdom Tree switches the whole 32KB ROM area at once. The bank number is taken from
+// the lower byte of the address written to, the data itself is ignored
+const BANK_SIZE: usize = 0x8000;
+
+pub struct WisdomTreeCartridge {
+    data: Vec<u8>,
+    current_bank: usize,
+}
+
+impl WisdomTreeCartridge {
+    pub fn new(data: Vec<u8>) -> WisdomTreeCartridge {
+        WisdomTreeCartridge { data, current_bank: 0 }
+    }
+}
+
+impl Cartridge for WisdomTreeCartridge {
+    fn read(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x7FFF => {
+                let offset = self.current_bank * BANK_SIZE + address as usize;
+                self.data[offset % self.data.len()]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: Address, data: u8) {
+        trace!("Wisdom Tree write {:#X} at {:#X}", data, address);
+
+        if let 0x0000..=0x3FFF = address {
+            self.current_bank = (address & 0xFF) as usize;
+        }
+    }
+
+    // Both halves move together, counted in 16KB banks like everything else
+    fn current_banks(&self) -> Banks {
+        Banks { rom0: 2 * self.current_bank, romx: 2 * self.current_bank + 1, ram: None }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod wisdom_tree_tests {
+    use super::*;
+
+    #[test]
+    fn should_switch_32kb_banks_by_address() {
+        let mut rom = vec![0; 4 * BANK_SIZE];
+        rom[2 * BANK_SIZE + 0x0100] = 0x12;
+        rom[2 * BANK_SIZE + 0x4100] = 0x34;
+        let mut cartridge = WisdomTreeCartridge::new(rom);
+
+        cartridge.write(0x0002, 0xFF);
+        assert_eq!(cartridge.read(0x0100), 0x12);
+        assert_eq!(cartridge.read(0x4100), 0x34);
+        assert_eq!(cartridge.current_banks(), Banks { rom0: 4, romx: 5, ram: None });
+
+        // Out of range banks wrap around, writes elsewhere are ignored
+        cartridge.write(0x4000, 0x01);
+        cartridge.write(0x2006, 0x00);
+        assert_eq!(cartridge.read(0x0100), 0x12);
+    }
+}
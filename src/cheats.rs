@@ -0,0 +1,239 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::{info, warn};
+use crate::memory::Address;
+
+// DMG frame length in clock cycles, GameShark codes are applied once per frame
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Code {
+    // Replaces a ROM byte as it is read, only where the original byte matches when a compare is given
+    GameGenie { address: Address, value: u8, compare: Option<u8> },
+    // Keeps writing a RAM byte every frame, into the given bank when it targets cartridge RAM
+    GameShark { bank: u8, address: Address, value: u8 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    InvalidCode(String),
+    OutOfRange(String, Address),
+}
+
+#[derive(Debug)]
+pub struct Cheat {
+    pub code: Code,
+    pub text: String,
+    pub description: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+// Cheat files live next to the ROM, sharing its name
+pub fn cheat_path(rom: &Path) -> PathBuf {
+    rom.with_extension("cht")
+}
+
+// One code per line followed by an optional description. Lines starting with '#' are
+// comments and codes starting with '!' are loaded disabled
+pub fn load(cheats: &mut Cheats, path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (enabled, line) = match line.strip_prefix('!') {
+            Some(line) => (false, line),
+            None => (true, line),
+        };
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let code = parts.next().unwrap_or_default();
+        let description = parts.next().unwrap_or_default().trim();
+
+        match cheats.add(code, description) {
+            Ok(index) => cheats.set_enabled(index, enabled),
+            Err(e) => warn!("Skipping line {} of {}: {}", number + 1, path.display(), e),
+        }
+    }
+
+    info!("Loaded {} cheats from {}", cheats.list().len(), path.display());
+    Ok(())
+}
+
+impl Cheats {
+    pub fn add(&mut self, text: &str, description: &str) -> Result<usize, CheatError> {
+        let code = text.parse()?;
+        self.cheats.push(Cheat {
+            code,
+            text: text.to_ascii_uppercase(),
+            description: description.to_owned(),
+            enabled: true,
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    pub fn report(&self) {
+        for (index, cheat) in self.cheats.iter().enumerate() {
+            let state = if cheat.enabled { "on" } else { "off" };
+            info!("Cheat #{} [{}] {} {}", index, state, cheat.text, cheat.description);
+        }
+    }
+
+    // Game Genie sits between the cartridge and the console, so it only sees ROM reads
+    pub fn patch_rom(&self, address: Address, original: u8) -> u8 {
+        self.cheats.iter()
+            .filter(|cheat| cheat.enabled)
+            .find_map(|cheat| match &cheat.code {
+                Code::GameGenie { address: target, value, compare }
+                    if *target == address && compare.is_none_or(|compare| compare == original) => Some(*value),
+                _ => None,
+            })
+            .unwrap_or(original)
+    }
+
+    // The mapper has no generic way to switch banks for us, so cartridge RAM codes wait for
+    // their bank to be mapped. Work RAM is not banked on DMG, the bank byte means nothing there
+    pub fn frame_writes(&self, ram_bank: Option<usize>) -> impl Iterator<Item = (Address, u8)> + '_ {
+        self.cheats.iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(move |cheat| match cheat.code {
+                Code::GameShark { bank, address, value } if address >= 0xC000 || ram_bank == Some(bank as usize) => {
+                    Some((address, value))
+                }
+                _ => None,
+            })
+    }
+}
+
+impl FromStr for Code {
+    type Err = CheatError;
+
+    // Game Genie codes come as ABC-DEF or ABC-DEF-GHI, GameShark codes as 8 plain digits
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || CheatError::InvalidCode(text.to_owned());
+        let digits: Vec<u8> = text.chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        let byte = |index: usize| (digits[index] << 4) | digits[index + 1];
+
+        match (digits.len(), text.contains('-')) {
+            // https://gamehacking.org/faqs/hackv500c.html
+            (6, _) | (9, _) => {
+                let address = u16::from_be_bytes([(digits[5] << 4) | digits[2], (digits[3] << 4) | digits[4]]) ^ 0xF000;
+                if address >= 0x8000 {
+                    return Err(CheatError::OutOfRange(text.to_owned(), address));
+                }
+                // The compare byte is scrambled across the third group, skipping its middle digit
+                let compare = match digits.len() {
+                    9 => Some(((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xBA),
+                    _ => None,
+                };
+                Ok(Code::GameGenie { address, value: byte(0), compare })
+            }
+            // Bank, value and a little endian address
+            (8, false) => {
+                let address = u16::from_le_bytes([byte(4), byte(6)]);
+                if !(0xA000..=0xDFFF).contains(&address) {
+                    return Err(CheatError::OutOfRange(text.to_owned(), address));
+                }
+                Ok(Code::GameShark { bank: byte(0), value: byte(2), address })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidCode(code) => {
+                write!(f, "{} is neither a Game Genie (ABC-DEF-GHI) nor a GameShark (01VVAAAA) code", code)
+            }
+            CheatError::OutOfRange(code, address) => write!(f, "{} targets {:#06X}, out of its range", code, address),
+        }
+    }
+}
+
+impl error::Error for CheatError {}
+
+#[cfg(test)]
+mod cheats_tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_game_genie_codes() {
+        assert_eq!("00A-17B-C49".parse(), Ok(Code::GameGenie { address: 0x4A17, value: 0x00, compare: Some(0xC8) }));
+        assert_eq!("3EB-89F".parse(), Ok(Code::GameGenie { address: 0x0B89, value: 0x3E, compare: None }));
+        assert!(matches!("01A-B77".parse::<Code>(), Err(CheatError::OutOfRange(_, 0x8AB7))));
+    }
+
+    #[test]
+    fn should_decode_gameshark_codes() {
+        assert_eq!("010238CD".parse(), Ok(Code::GameShark { bank: 0x01, value: 0x02, address: 0xCD38 }));
+        assert!(matches!("01020080".parse::<Code>(), Err(CheatError::OutOfRange(_, 0x8000))));
+        assert!(matches!("0102XYZW".parse::<Code>(), Err(CheatError::InvalidCode(_))));
+    }
+
+    #[test]
+    fn should_patch_rom_reads_matching_compare() {
+        let mut cheats = Cheats::default();
+        cheats.add("00A-17B-C49", "").unwrap();
+
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0x00);
+        assert_eq!(cheats.patch_rom(0x4A17, 0x12), 0x12);
+        assert_eq!(cheats.patch_rom(0x4A18, 0xC8), 0xC8);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0xC8);
+    }
+
+    #[test]
+    fn should_load_cheat_files() {
+        let path = std::env::temp_dir().join(format!("rustboy-cheats-{}.cht", std::process::id()));
+        fs::write(&path, "# Infinite everything\n010238CD  Lives\n!01FF39CD Health\nnot-a-code\n").unwrap();
+
+        let mut cheats = Cheats::default();
+        load(&mut cheats, &path).unwrap();
+
+        assert_eq!(cheats.list().len(), 2);
+        assert_eq!(cheats.list()[0].description, "Lives");
+        assert!(!cheats.list()[1].enabled);
+        assert_eq!(cheats.frame_writes(None).collect::<Vec<_>>(), vec![(0xCD38, 0x02)]);
+    }
+
+    #[test]
+    fn should_write_cartridge_ram_only_into_code_bank() {
+        let mut cheats = Cheats::default();
+        cheats.add("03991AA0", "").unwrap();
+        cheats.add("03420AC0", "").unwrap();
+
+        assert_eq!(cheats.frame_writes(Some(3)).collect::<Vec<_>>(), vec![(0xA01A, 0x99), (0xC00A, 0x42)]);
+        assert_eq!(cheats.frame_writes(Some(0)).collect::<Vec<_>>(), vec![(0xC00A, 0x42)]);
+        assert_eq!(cheats.frame_writes(None).collect::<Vec<_>>(), vec![(0xC00A, 0x42)]);
+    }
+}
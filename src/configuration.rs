@@ -2,19 +2,269 @@
 use log;
 use clap::Clap;
 use std::path::PathBuf;
+use std::str::FromStr;
+use crate::cartridge::clock::{self, ClockKind};
+use crate::cartridge::header::CgbSupport;
+use crate::cartridge::mapper::Mapper;
+use crate::cartridge::save::{self, FooterFormat};
+use crate::power_on::InitSetting;
+use crate::soc::ppu::Renderer;
 
 #[derive(Clap, Debug)]
 #[clap(name = "basic")]
 pub struct Config {
 
+    /// Game ROM to run
     #[clap(short, long, parse(from_os_str))]
-    cartridge: PathBuf,
+    pub cartridge: Option<PathBuf>,
+
+    /// Boots with an empty cartridge slot
+    #[clap(long, conflicts_with = "cartridge")]
+    pub no_cartridge: bool,
+
+    /// ROM swapped in while running, the current cartridge is ejected and its save flushed
+    #[clap(long, parse(from_os_str))]
+    pub swap: Option<PathBuf>,
+
+    /// Frame at which --swap happens
+    #[clap(long, default_value = "60")]
+    pub swap_frame: u64,
+
+    /// Frames the slot stays empty during --swap, cartridge reads see open bus meanwhile
+    #[clap(long, default_value = "0")]
+    pub swap_gap: u64,
+
+    /// Frames the contacts of the --swap cartridge take to settle, data lines not touching
+    /// yet read and write as 1
+    #[clap(long, default_value = "0")]
+    pub swap_bounce: u64,
+
+    /// ROM to pick inside a zip archive, the first .gb/.gbc/.sgb entry otherwise
+    #[clap(long)]
+    pub entry: Option<String>,
+
+    /// IPS, UPS or BPS patch applied on load, a patch named after the ROM otherwise
+    #[clap(short, long, parse(from_os_str))]
+    pub patch: Option<PathBuf>,
+
+    /// Game Genie or GameShark code to enable, on top of the cheat file named after the ROM
+    #[clap(long, number_of_values = 1)]
+    pub cheat: Vec<String>,
+
+    /// No-Intro DAT file identifying the exact ROM dump
+    #[clap(long, parse(from_os_str))]
+    pub database: Option<PathBuf>,
 
     #[clap(short, long, default_value = "INFO")]
-    log_level: String,
+    pub log_level: String,
+
+    #[clap(short, long)]
+    pub gui: bool,
+
+    /// Still PNG or directory of PNG frames seen by the Pocket Camera sensor
+    #[clap(long, parse(from_os_str))]
+    pub camera: Option<PathBuf>,
+
+    /// Time source of cartridge clocks: system, frozen or cycles
+    #[clap(long, default_value = "system")]
+    pub clock: ClockKind,
+
+    /// UNIX time the frozen and cycles clocks start from, the host time otherwise
+    #[clap(long)]
+    pub clock_start: Option<u64>,
+
+    /// Moves cartridge clocks forward, as 90, 45s, 30m, 12h or 3d
+    #[clap(long, default_value = "0", parse(try_from_str = clock::parse_duration))]
+    pub clock_offset: u64,
+
+    /// Power-on RAM contents: zero, ff, random, dmg or cgb, or region=mode for one of wram,
+    /// hram, vram, oam and sram
+    #[clap(long, number_of_values = 1)]
+    pub memory_init: Vec<InitSetting>,
+
+    /// Seed of the random power-on RAM contents
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+
+    /// PPU backend: scanline, or fifo for games relying on mid-line raster effects
+    #[clap(long, default_value = "scanline")]
+    pub ppu: Renderer,
+
+    /// Saves a PNG of the screen once --screenshot-frame is reached
+    #[clap(long, parse(from_os_str))]
+    pub screenshot: Option<PathBuf>,
+
+    /// Frame at which --screenshot is taken
+    #[clap(long, default_value = "60")]
+    pub screenshot_frame: u64,
+
+    /// Integer scale of --screenshot
+    #[clap(long, default_value = "1")]
+    pub screenshot_scale: usize,
+
+    /// Saves a PNG of the screen into this directory each time Enter is pressed in the
+    /// terminal, named after the frame
+    #[clap(long, parse(from_os_str))]
+    pub screenshot_dir: Option<PathBuf>,
+
+    /// Dumps tiles, tile maps, palettes and OAM into a directory once --vram-dump-frame is
+    /// reached
+    #[clap(long, parse(from_os_str))]
+    pub vram_dump: Option<PathBuf>,
+
+    /// Frame at which --vram-dump happens
+    #[clap(long, default_value = "60")]
+    pub vram_dump_frame: u64,
+
+    /// Records every frame into a .y4m, .gif or raw RGB24 .rgb file
+    #[clap(long, parse(from_os_str))]
+    pub record: Option<PathBuf>,
+
+    /// Lets the CPU into VRAM and OAM while the PPU is using them, for debugging
+    #[clap(long)]
+    pub ignore_access_restrictions: bool,
+
+    /// Leaves the background out of the picture, the game still sees it enabled
+    #[clap(long)]
+    pub hide_background: bool,
+
+    /// Leaves the window out of the picture, the background shows through
+    #[clap(long)]
+    pub hide_window: bool,
+
+    /// Leaves every sprite out of the picture
+    #[clap(long)]
+    pub hide_sprites: bool,
+
+    /// Leaves the sprite at this OAM index, 0 to 39, out of the picture. Can be repeated
+    #[clap(long, number_of_values = 1)]
+    pub hide_sprite: Vec<usize>,
+
+    /// Draws every sprite of a line instead of the first ten, to get rid of flicker
+    #[clap(long)]
+    pub unlimited_sprites: bool,
+
+    /// Forces a mapper when the header lies about it
+    #[clap(short, long)]
+    pub mapper: Option<Mapper>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clap, Debug)]
+pub enum Command {
+    /// Prints the header of ROM files, or of every ROM inside directories
+    Info(InfoOptions),
+    /// Fixes the header of a homebrew ROM, rgbfix style
+    Fix(FixOptions),
+    /// Converts battery saves between raw, clock footer and flash cart padded layouts
+    ConvertSave(ConvertSaveOptions),
+}
+
+#[derive(Clap, Debug)]
+pub struct InfoOptions {
+    /// ROM files, archives or directories
+    #[clap(parse(from_os_str), required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Output format, table or json
+    #[clap(short, long, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// No-Intro DAT file identifying the exact ROM dumps
+    #[clap(long, parse(from_os_str))]
+    pub database: Option<PathBuf>,
+}
 
+#[derive(Clap, Debug)]
+pub struct FixOptions {
+    /// ROM file to fix
+    #[clap(parse(from_os_str))]
+    pub path: PathBuf,
+
+    /// Where to write the fixed ROM, in place otherwise
+    #[clap(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Writes the Nintendo logo checked by the boot ROM
     #[clap(short, long)]
-    gui: bool
+    pub logo: bool,
+
+    /// Game title, up to 16 characters or 15 with a CGB flag
+    #[clap(short, long)]
+    pub title: Option<String>,
+
+    /// CGB support, none, compatible or only
+    #[clap(long)]
+    pub cgb: Option<CgbSupport>,
+
+    /// Flags the game as SGB enhanced
+    #[clap(long)]
+    pub sgb: bool,
+
+    /// Cartridge type byte, e.g. 0x1B for MBC5+RAM+BATTERY
+    #[clap(long, parse(try_from_str = parse_byte))]
+    pub cartridge_type: Option<u8>,
+
+    /// RAM size code, e.g. 0x03 for 32KB
+    #[clap(long, parse(try_from_str = parse_byte))]
+    pub ram_size: Option<u8>,
+
+    /// Pads the ROM to a power of two number of banks and updates its size
+    #[clap(short, long)]
+    pub pad: bool,
+
+    /// Byte used for padding
+    #[clap(long, default_value = "0xFF", parse(try_from_str = parse_byte))]
+    pub pad_value: u8,
+}
+
+#[derive(Clap, Debug)]
+pub struct ConvertSaveOptions {
+    /// Save file to convert
+    #[clap(parse(from_os_str))]
+    pub input: PathBuf,
+
+    /// Converted save file
+    #[clap(parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// Clock footer to write, keep, none, 48 (BGB, VBA-M), 44 bytes (older VBA) or huc3
+    /// (SameBoy's HuC3 layout)
+    #[clap(long, default_value = "keep")]
+    pub footer: FooterFormat,
+
+    /// RAM size in KB, e.g. 32 or 64 for flash carts or the game's own size to undo padding
+    #[clap(long, parse(try_from_str = save::parse_kilobytes))]
+    pub ram_size: Option<usize>,
+}
+
+// Header bytes are usually given in hexadecimal
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| format!("Invalid byte {}: {}", value, e))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format {}, expected table or json", format)),
+        }
+    }
 }
 
 // impl From<ArgMatches> for Config {
@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use log::info;
+use crate::cartridge::save;
+use crate::configuration::ConvertSaveOptions;
+
+pub fn run(options: &ConvertSaveOptions) -> io::Result<()> {
+    let data = fs::read(&options.input)?;
+    let converted = save::convert(&data, options.footer, options.ram_size);
+
+    fs::write(&options.output, &converted)?;
+    info!(
+        "Converted {} ({} bytes) into {} ({} bytes)",
+        options.input.display(),
+        data.len(),
+        options.output.display(),
+        converted.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod convert_tests {
+    use super::*;
+    use crate::cartridge::rtc::{RtcFooter, FOOTER_SIZE};
+    use crate::cartridge::save::FooterFormat;
+
+    #[test]
+    fn should_convert_save_file_for_flash_carts() {
+        let input = std::env::temp_dir().join(format!("rustboy-convert-{}.sav", std::process::id()));
+        let output = input.with_extension("srm");
+        let footer = RtcFooter { registers: [1, 2, 3, 4, 0], latched: [0; 5], timestamp: 42 };
+        let mut data = vec![0x11; 0x2000];
+        data.extend(footer.to_bytes(true));
+        fs::write(&input, &data).unwrap();
+
+        let options = ConvertSaveOptions {
+            input: input.clone(),
+            output: output.clone(),
+            footer: FooterFormat::Modern,
+            ram_size: Some(0x8000),
+        };
+        run(&options).unwrap();
+
+        let converted = fs::read(&output).unwrap();
+        assert_eq!(converted.len(), 0x8000 + FOOTER_SIZE);
+        assert_eq!(converted[..0x2000], data[..0x2000]);
+        assert!(converted[0x2000..0x8000].iter().all(|&byte| byte == 0xFF));
+        assert_eq!(converted[0x8000..], footer.to_bytes(false)[..]);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn should_fail_on_missing_input() {
+        let options = ConvertSaveOptions {
+            input: std::env::temp_dir().join("rustboy-convert-missing.sav"),
+            output: std::env::temp_dir().join("rustboy-convert-missing.srm"),
+            footer: FooterFormat::Keep,
+            ram_size: None,
+        };
+        assert!(run(&options).is_err());
+        assert!(!options.output.exists());
+    }
+}
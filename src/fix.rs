@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use log::{info, warn};
+use crate::cartridge::cartridge::NINTENDO_LOGO;
+use crate::cartridge::header::*;
+use crate::configuration::FixOptions;
+use crate::utils::hilo;
+
+// The smallest cartridge holds two banks
+const MIN_ROM_BANKS: usize = 2;
+
+pub fn run(options: &FixOptions) -> io::Result<()> {
+    let mut blob = fs::read(&options.path)?;
+    if blob.len() < HEADER_END {
+        let error = HeaderError::Truncated(blob.len());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+    }
+
+    fix(&mut blob, options);
+
+    let header = CartridgeHeader::parse(&blob).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    header.report_warnings(&blob);
+
+    let output = options.output.as_ref().unwrap_or(&options.path);
+    fs::write(output, &blob)?;
+    info!("Fixed {} into {} ({} bytes)", options.path.display(), output.display(), blob.len());
+    Ok(())
+}
+
+// Same order as rgbfix, the checksums go last as they cover every other change
+pub fn fix(blob: &mut Vec<u8>, options: &FixOptions) {
+    if options.logo {
+        blob[LOGO..LOGO + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+    }
+
+    if let Some(cgb) = options.cgb {
+        blob[CGB_FLAG] = match cgb {
+            CgbSupport::None => 0x00,
+            CgbSupport::Compatible => 0x80,
+            CgbSupport::Only => 0xC0,
+        };
+    }
+
+    if let Some(title) = &options.title {
+        write_title(blob, title);
+    }
+
+    if options.sgb {
+        blob[SGB_FLAG] = 0x03;
+        if blob[OLD_LICENSEE] != USE_NEW_LICENSEE {
+            warn!("SGB functions stay disabled unless the old licensee code is {:#04X}", USE_NEW_LICENSEE);
+        }
+    }
+
+    if let Some(cartridge_type) = options.cartridge_type {
+        blob[CARTRIDGE_TYPE] = cartridge_type;
+    }
+
+    if let Some(ram_size) = options.ram_size {
+        blob[RAM_SIZE] = ram_size;
+    }
+
+    if options.pad {
+        pad(blob, options.pad_value);
+    }
+
+    blob[HEADER_CHECKSUM] = header_checksum(blob);
+    let (high, low) = hilo(global_checksum(blob));
+    blob[GLOBAL_CHECKSUM] = high;
+    blob[GLOBAL_CHECKSUM + 1] = low;
+}
+
+// Colour games give up the last title byte to their CGB flag
+fn write_title(blob: &mut [u8], title: &str) {
+    let end = if blob[CGB_FLAG] & 0x80 != 0 { CGB_FLAG } else { NEW_LICENSEE };
+    let length = title.len().min(end - TITLE);
+    if length < title.len() {
+        warn!("Title {} truncated to {} characters", title, length);
+    }
+
+    blob[TITLE..end].fill(0);
+    blob[TITLE..TITLE + length].copy_from_slice(&title.as_bytes()[..length]);
+}
+
+// The ROM size byte can only describe a power of two number of banks
+fn pad(blob: &mut Vec<u8>, value: u8) {
+    let banks = blob.len().div_ceil(ROM_BANK_SIZE).next_power_of_two().max(MIN_ROM_BANKS);
+    blob.resize(banks * ROM_BANK_SIZE, value);
+    blob[ROM_SIZE] = (banks / MIN_ROM_BANKS).trailing_zeros() as u8;
+}
+
+#[cfg(test)]
+mod fix_tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn options() -> FixOptions {
+        FixOptions {
+            path: PathBuf::new(),
+            output: None,
+            logo: true,
+            title: Some("HOMEBREW".to_owned()),
+            cgb: None,
+            sgb: false,
+            cartridge_type: None,
+            ram_size: None,
+            pad: true,
+            pad_value: 0xFF,
+        }
+    }
+
+    #[test]
+    fn should_produce_valid_header() {
+        let mut blob = vec![0; 0x5000];
+        fix(&mut blob, &options());
+
+        let header = CartridgeHeader::parse(&blob).unwrap();
+        assert_eq!(blob.len(), 0x8000);
+        assert_eq!(blob[0x7FFF], 0xFF);
+        assert_eq!(header.title, "HOMEBREW");
+        assert_eq!(header.validate(&blob), vec![]);
+    }
+
+    #[test]
+    fn should_pad_to_power_of_two_banks() {
+        let mut blob = vec![0; 5 * ROM_BANK_SIZE];
+        fix(&mut blob, &FixOptions { cartridge_type: Some(0x19), ..options() });
+
+        let header = CartridgeHeader::parse(&blob).unwrap();
+        assert_eq!(header.rom_banks(), Some(8));
+        assert_eq!(header.validate(&blob), vec![]);
+    }
+
+    #[test]
+    fn should_keep_cgb_flag_out_of_title() {
+        let mut blob = vec![0; 0x8000];
+        let options = FixOptions { cgb: Some(CgbSupport::Only), title: Some("A VERY LONG GAME TITLE".to_owned()), ..options() };
+        fix(&mut blob, &options);
+
+        let header = CartridgeHeader::parse(&blob).unwrap();
+        assert_eq!(header.cgb, CgbSupport::Only);
+        assert_eq!(header.title, "A VERY LONG GAM");
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+use crate::cartridge::database::{Database, DumpStatus, Identification};
+use crate::cartridge::header::{CartridgeHeader, HeaderWarning};
+use crate::cartridge::loader;
+use crate::cartridge::mapper::{self, Mapper};
+use crate::configuration::{InfoOptions, OutputFormat};
+
+struct Summary<'a> {
+    header: CartridgeHeader,
+    dump: Option<Identification<'a>>,
+    mapper: Option<Mapper>,
+    size: usize,
+    warnings: Vec<HeaderWarning>,
+}
+
+// A failing file is reported in its own row instead of stopping the whole run
+struct Entry<'a> {
+    path: PathBuf,
+    summary: Result<Summary<'a>, String>,
+}
+
+pub fn run(options: &InfoOptions) -> io::Result<()> {
+    let database = match &options.database {
+        Some(path) => Some(Database::load(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+        None => None,
+    };
+
+    let entries: Vec<Entry> = options.paths
+        .iter()
+        .flat_map(|path| expand(path))
+        .map(|path| match path {
+            Ok(path) => Entry { summary: inspect(&path, database.as_ref()), path },
+            Err((path, e)) => Entry { path, summary: Err(e.to_string()) },
+        })
+        .collect();
+
+    match options.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&to_json(&entries))?),
+        OutputFormat::Table => print!("{}", table(&entries)),
+    }
+    Ok(())
+}
+
+fn expand(path: &Path) -> Vec<Result<PathBuf, (PathBuf, io::Error)>> {
+    if !path.is_dir() {
+        return vec![Ok(path.to_path_buf())];
+    }
+
+    match fs::read_dir(path) {
+        Ok(entries) => {
+            let mut roms: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| loader::is_loadable(path))
+                .collect();
+            roms.sort();
+            roms.into_iter().map(Ok).collect()
+        }
+        Err(e) => vec![Err((path.to_path_buf(), e))],
+    }
+}
+
+fn inspect<'a>(path: &Path, database: Option<&'a Database>) -> Result<Summary<'a>, String> {
+    let blob = loader::load(path, None).map_err(|e| e.to_string())?.blob;
+    let header = CartridgeHeader::parse(&blob).map_err(|e| e.to_string())?;
+    let warnings = header.validate(&blob);
+
+    Ok(Summary {
+        dump: database.and_then(|database| database.identify(&blob)),
+        mapper: mapper::detect(&blob),
+        size: blob.len(),
+        warnings,
+        header,
+    })
+}
+
+fn to_json(entries: &[Entry]) -> Value {
+    let entries = entries.iter().map(|entry| match &entry.summary {
+        Ok(summary) => json!({
+            "file": entry.path.display().to_string(),
+            "title": summary.header.title,
+            "manufacturer_code": summary.header.manufacturer_code,
+            "cartridge_type": summary.header.cartridge_type,
+            "mapper": summary.mapper.map(|mapper| mapper.to_string()),
+            "rom_size": summary.header.rom_size(),
+            "rom_banks": summary.header.rom_banks(),
+            "file_size": summary.size,
+            "ram_size": summary.header.ram_size(),
+            "cgb": format!("{:?}", summary.header.cgb),
+            "sgb": summary.header.sgb,
+            "destination": summary.header.destination(),
+            "licensee": summary.header.licensee(),
+            "version": summary.header.version,
+            "header_checksum": summary.header.header_checksum,
+            "global_checksum": summary.header.global_checksum,
+            "warnings": summary.warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            "dump": summary.dump.as_ref().map(|dump| json!({
+                "name": dump.entry.name,
+                "region": dump.entry.region(),
+                "revision": dump.entry.revision(),
+                "status": format!("{:?}", dump.entry.status),
+                "overdump": dump.overdump,
+                "suggested_mapper": dump.suggested_mapper.map(|mapper| mapper.to_string()),
+            })),
+        }),
+        Err(e) => json!({
+            "file": entry.path.display().to_string(),
+            "error": e,
+        }),
+    });
+    Value::Array(entries.collect())
+}
+
+fn status(summary: &Summary) -> String {
+    let mut problems: Vec<String> = summary.warnings.iter().map(|w| w.to_string()).collect();
+
+    if let Some(dump) = &summary.dump {
+        if dump.entry.status == DumpStatus::BadDump {
+            problems.push("bad dump".to_owned());
+        }
+        if dump.overdump {
+            problems.push("overdump".to_owned());
+        }
+        if let Some(mapper) = dump.suggested_mapper {
+            problems.push(format!("needs {} mapper", mapper));
+        }
+    }
+
+    if problems.is_empty() { "ok".to_owned() } else { problems.join("; ") }
+}
+
+fn table(entries: &[Entry]) -> String {
+    let header = ["FILE", "TITLE", "MAPPER", "ROM", "RAM", "CGB", "SGB", "LICENSEE", "VER", "DUMP", "STATUS"];
+
+    let rows: Vec<Vec<String>> = entries.iter().map(|entry| {
+        let file = entry.path.display().to_string();
+        match &entry.summary {
+            Ok(summary) => vec![
+                file,
+                summary.header.title.clone(),
+                summary.mapper.map_or(format!("{:#04X}?", summary.header.cartridge_type), |m| m.to_string()),
+                summary.header.rom_size().map_or("?".to_owned(), |size| format!("{}K", size / 1024)),
+                summary.header.ram_size().map_or("?".to_owned(), |size| format!("{}K", size / 1024)),
+                format!("{:?}", summary.header.cgb),
+                if summary.header.sgb { "yes" } else { "no" }.to_owned(),
+                summary.header.licensee().to_owned(),
+                summary.header.version.to_string(),
+                summary.dump.as_ref().map_or("-".to_owned(), |dump| dump.entry.name.clone()),
+                status(summary),
+            ],
+            Err(e) => {
+                let mut row = vec![file];
+                row.resize(header.len() - 1, "-".to_owned());
+                row.push(format!("error: {}", e));
+                row
+            }
+        }
+    }).collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).chain(Some(header[column].len())).max().unwrap_or(0))
+        .collect();
+
+    let format_row = |row: Vec<&str>| {
+        let line: Vec<String> = row.iter().zip(&widths).map(|(cell, &width)| format!("{:width$}", cell, width = width)).collect();
+        format!("{}\n", line.join("  ").trim_end())
+    };
+
+    let mut table = format_row(header.to_vec());
+    for row in &rows {
+        table.push_str(&format_row(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
+
+#[cfg(test)]
+mod info_tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry<'static>> {
+        let mut blob = vec![0; 0x8000];
+        blob[0x0134..0x013A].copy_from_slice(b"TETRIS");
+        blob[0x0147] = 0x01;
+        let header = CartridgeHeader::parse(&blob).unwrap();
+
+        vec![
+            Entry {
+                path: PathBuf::from("tetris.gb"),
+                summary: Ok(Summary {
+                    warnings: header.validate(&blob),
+                    header,
+                    dump: None,
+                    mapper: mapper::detect(&blob),
+                    size: blob.len(),
+                }),
+            },
+            Entry { path: PathBuf::from("broken.gb"), summary: Err("ROM of 12 bytes".to_owned()) },
+        ]
+    }
+
+    #[test]
+    fn should_describe_entries_in_json() {
+        let json = to_json(&entries());
+
+        assert_eq!(json[0]["file"], "tetris.gb");
+        assert_eq!(json[0]["title"], "TETRIS");
+        assert_eq!(json[0]["cartridge_type"], 1);
+        assert_eq!(json[0]["mapper"], "MBC1");
+        assert_eq!(json[0]["rom_size"], 0x8000);
+        assert_eq!(json[0]["dump"], Value::Null);
+        assert!(!json[0]["warnings"].as_array().unwrap().is_empty());
+        assert_eq!(json[1], json!({ "file": "broken.gb", "error": "ROM of 12 bytes" }));
+    }
+
+    #[test]
+    fn should_line_up_table_columns() {
+        let table = table(&entries());
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("FILE       TITLE   MAPPER"));
+        assert!(lines[1].starts_with("tetris.gb  TETRIS  MBC1"));
+        assert!(lines[2].starts_with("broken.gb  -       -"));
+        assert!(lines[2].ends_with("error: ROM of 12 bytes"));
+        assert_eq!(lines[0].find("MAPPER"), lines[1].find("MBC1"));
+    }
+}
@@ -6,50 +6,254 @@ mod tests;
 mod utils;
 mod cartridge;
 mod configuration;
+mod info;
+mod fix;
+mod convert;
+mod cheats;
+mod power_on;
+mod recording;
+mod screenshot;
+mod vram_dump;
+mod schedule;
 
 use chrono;
 use soc::cpu::CPU;
 use log::{debug, error, info};
 use memory::MemorySpace;
 use cartridge::cartridge::Cartridge;
+use cartridge::{camera::PocketCamera, clock, database::Database, empty::EmptySlot, header::{CartridgeHeader, CgbSupport}, image_source, loader, mapper::{self, Mapper}, patch, save};
 use fern::colors::{Color, ColoredLevelConfig};
 use fern::Output;
-use std::{fs::File, io::{Read, BufReader}, str::FromStr};
-use color_eyre::eyre::Result;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use color_eyre::eyre::{eyre, Result};
 use clap::Clap;
-use configuration::Config;
+use configuration::{Command, Config};
+use power_on::MemoryInit;
+use cheats::Cheats;
+use schedule::{Action, Schedule};
+use soc::ppu::{Layers, OAM_ENTRIES};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    // let opts: Opts = Opts::parse();
-    let log_level = "TRACE";
-    let cartridge = "Tetris.gb";
+    let config = Config::parse();
 
-    setup_logger(log_level);
+    if let Some(command) = &config.command {
+        // Results are printed to stdout, keep the log out of their way
+        setup_logger(&config.log_level, std::io::stderr());
+        match command {
+            Command::Info(options) => info::run(options)?,
+            Command::Fix(options) => fix::run(options)?,
+            Command::ConvertSave(options) => convert::run(options)?,
+        }
+        return Ok(());
+    }
+
+    setup_logger(&config.log_level, std::io::stdout());
 
     info!("Starting rustboy emulator");
 
-    let file = File::open(cartridge).expect("Cartridge not found");
-    let mut reader = BufReader::new(file);
-    let mut blob = Vec::new();
+    let memory_init = MemoryInit::new(&config.memory_init, config.seed);
+    let database = config.database.as_deref().map(Database::load).transpose()?;
+
+    if config.no_cartridge {
+        let mut cpu = CPU::new(MemorySpace::new(Box::new(EmptySlot)));
+        setup_ppu(&mut cpu.memory, &config)?;
+        cpu.power_on(&memory_init);
+        if let Some(path) = &config.record {
+            cpu.memory.record(recording::open(path)?);
+        }
+        let save_path = run(&mut cpu, &config, &memory_init, database.as_ref(), None)?;
+        return finish(&mut cpu, save_path.as_deref());
+    }
 
-    reader.read_to_end(&mut blob);
+    let rom_path = config.cartridge.as_ref().ok_or_else(|| eyre!("No cartridge given, see --help"))?;
+    let LoadedCartridge { mut cartridge, header, logical_path } =
+        load_cartridge(rom_path, config.entry.as_deref(), config.patch.clone(), &config, database.as_ref(), &memory_init)?;
 
-    let mut cartridge: Box<dyn Cartridge> = cartridge::cartridge::decode_cartridge(blob);
-    cartridge.report();
+    let save_path = save::save_path(&logical_path);
+    save::load(cartridge.as_mut(), &save_path)?;
 
     let mut memory = MemorySpace::new(cartridge);
+
+    load_cheats(&mut memory, &logical_path, &config)?;
+    setup_ppu(&mut memory, &config)?;
+    memory.ppu_mut().set_cgb(header.cgb != CgbSupport::None);
+    if let Some(path) = &config.record {
+        memory.record(recording::open(path)?);
+    }
     let mut cpu = CPU::new(memory);
+    cpu.power_on(&memory_init);
     info!("CPU execution started");
+    let save_path = run(&mut cpu, &config, &memory_init, database.as_ref(), Some(save_path))?;
+    finish(&mut cpu, save_path.as_deref())
+}
+
+// A cartridge built from a ROM file the way the options ask, the initial one and a --swap
+// one alike. Its battery save is left to the caller
+struct LoadedCartridge {
+    cartridge: Box<dyn Cartridge>,
+    header: CartridgeHeader,
+    logical_path: PathBuf,
+}
+
+fn load_cartridge(
+    rom_path: &Path,
+    entry: Option<&str>,
+    patch_path: Option<PathBuf>,
+    config: &Config,
+    database: Option<&Database>,
+    memory_init: &MemoryInit,
+) -> Result<LoadedCartridge> {
+    let loader::RomFile { mut blob, logical_path } = loader::load(rom_path, entry)?;
+
+    // Dumps are identified before patching, bug reports need the original one
+    let dump = database.and_then(|database| database.identify(&blob));
+
+    // The header is validated once patched, translations and hacks often rewrite it
+    if let Some(patch_path) = patch_path.or_else(|| patch::find(&logical_path)) {
+        info!("Applying patch {}", patch_path.display());
+        blob = patch::apply(&blob, &std::fs::read(&patch_path)?)?;
+    }
+
+    let header = CartridgeHeader::parse(&blob)?;
+    header.report();
+    header.report_warnings(&blob);
+
+    let mapper = match config.mapper {
+        Some(mapper) => mapper,
+        None => mapper::detect(&blob).ok_or_else(|| eyre!("Unsupported cartridge type in {}", rom_path.display()))?,
+    };
+
+    let mut cartridge: Box<dyn Cartridge> = match (&config.camera, mapper) {
+        (Some(path), Mapper::PocketCamera) => {
+            Box::new(PocketCamera::with_source(blob, image_source::open(path)?))
+        }
+        _ => cartridge::cartridge::decode_cartridge_as(blob, mapper),
+    };
+
+    if let Some(dump) = &dump {
+        dump.report(&cartridge.title());
+    }
+
+    // The clock is in place before the save footer makes it catch up with the time spent off
+    cartridge.set_clock(clock::build(config.clock, config.clock_start, config.clock_offset));
+
+    memory_init.fill_cartridge(cartridge.as_mut());
+    Ok(LoadedCartridge { cartridge, header, logical_path })
+}
 
-    cpu.run();
+// The cheat file of the game and the --cheat codes, replacing those of the previous game
+fn load_cheats(memory: &mut MemorySpace, logical_path: &Path, config: &Config) -> Result<()> {
+    *memory.cheats_mut() = Cheats::default();
+    cheats::load(memory.cheats_mut(), &cheats::cheat_path(logical_path))?;
+    for code in &config.cheat {
+        memory.cheats_mut().add(code, "")?;
+    }
+    memory.cheats().report();
+    Ok(())
+}
+
+// Runs until the CPU halts, carrying out frame-indexed options and screenshots asked for
+// from the terminal as their frame starts. Returns the save path of the cartridge left in
+// the slot
+fn run(
+    cpu: &mut CPU,
+    config: &Config,
+    memory_init: &MemoryInit,
+    database: Option<&Database>,
+    mut save_path: Option<PathBuf>,
+) -> Result<Option<PathBuf>> {
+    let mut schedule = Schedule::from_config(config);
+    let presses = config.screenshot_dir.as_ref().map(|_| screenshot::enter_presses());
+
+    loop {
+        let frame = cpu.memory.frame();
+        for action in schedule.due(frame) {
+            match action {
+                Action::Screenshot(path) => screenshot::save(cpu.memory.ppu(), config.screenshot_scale, &path)?,
+                Action::VramDump(directory) => vram_dump::dump(cpu.memory.ppu(), &directory)?,
+                Action::Eject => eject(cpu, save_path.take().as_deref())?,
+                Action::Insert(path) => save_path = Some(insert(cpu, &path, config, memory_init, database)?),
+            }
+        }
+        if let (Some(presses), Some(directory)) = (&presses, &config.screenshot_dir) {
+            if presses.try_iter().count() > 0 {
+                let path = directory.join(format!("frame-{}.png", frame));
+                screenshot::save(cpu.memory.ppu(), config.screenshot_scale, &path)?;
+            }
+        }
+
+        if presses.is_none() && schedule.is_empty() {
+            cpu.run();
+        }
+        if cpu.halted {
+            return Ok(save_path);
+        }
+        cpu.run_until_frame(frame + 1);
+    }
+}
+
+// The cartridge in the slot goes out with its save flushed, reads see open bus until the
+// next one comes in
+fn eject(cpu: &mut CPU, save_path: Option<&Path>) -> Result<()> {
+    match save_path {
+        Some(path) => {
+            save::eject(&mut cpu.memory, path)?;
+        }
+        None => {
+            cpu.memory.eject();
+        }
+    }
+    Ok(())
+}
+
+// A --swap cartridge comes in through the same pipeline as the initial one, with its save,
+// cheats and palette mode, while its contacts settle. Returns where its save goes
+fn insert(cpu: &mut CPU, rom_path: &Path, config: &Config, memory_init: &MemoryInit, database: Option<&Database>) -> Result<PathBuf> {
+    let LoadedCartridge { cartridge, header, logical_path } = load_cartridge(rom_path, None, None, config, database, memory_init)?;
+    let save_path = save::save_path(&logical_path);
+    save::insert(&mut cpu.memory, cartridge, &save_path)?;
+    cpu.memory.loosen_contacts(config.swap_bounce, config.seed);
+
+    load_cheats(&mut cpu.memory, &logical_path, config)?;
+    cpu.memory.ppu_mut().set_cgb(header.cgb != CgbSupport::None);
+    Ok(save_path)
+}
+
+fn finish(cpu: &mut CPU, save_path: Option<&Path>) -> Result<()> {
     info!("Execution finished");
+    cpu.memory.stop_recording()?;
+    if let Some(path) = save_path {
+        save::store(cpu.memory.cartridge(), path)?;
+    }
+    Ok(())
+}
+
+// Renderer and debugging options, the same whatever is in the cartridge slot
+fn setup_ppu(memory: &mut MemorySpace, config: &Config) -> Result<()> {
+    let mut hidden_sprites = 0;
+    for &index in &config.hide_sprite {
+        if index >= OAM_ENTRIES {
+            return Err(eyre!("No sprite {} to hide, OAM holds {}", index, OAM_ENTRIES));
+        }
+        hidden_sprites |= 1 << index;
+    }
 
+    memory.ppu_mut().set_renderer(config.ppu);
+    memory.ppu_mut().set_layers(Layers {
+        background: !config.hide_background,
+        window: !config.hide_window,
+        sprites: !config.hide_sprites,
+        hidden_sprites,
+        unlimited_sprites: config.unlimited_sprites,
+    });
+    memory.ignore_access_restrictions(config.ignore_access_restrictions);
     Ok(())
 }
 
-fn setup_logger(level: &str) {
+fn setup_logger(level: &str, output: impl Into<Output>) {
     let level = log::LevelFilter::from_str(level).expect("Invalid logging level");
 
     let colors = ColoredLevelConfig::new()
@@ -70,7 +274,7 @@ fn setup_logger(level: &str) {
             ));
         })
         .level(level)
-        .chain(std::io::stdout())
+        .chain(output)
         .apply()
         .expect("Error configuring logger");
 }
@@ -1,7 +1,14 @@
-use log::{debug, info, trace};
+use log::{debug, error, info, trace};
 use std::ops::{Range, RangeInclusive};
-use std::{fmt, ops};
+use std::cell::Cell;
+use std::{fmt, io, mem, ops};
 use crate::cartridge::cartridge::Cartridge;
+use crate::cartridge::empty::EmptySlot;
+use crate::cheats::{Cheats, CYCLES_PER_FRAME};
+use crate::power_on::{MemoryInit, Region, Xorshift};
+use crate::recording::FrameSink;
+use crate::screenshot;
+use crate::soc::ppu::{self, Ppu};
 
 pub(crate) type Address = u16;
 type Byte = u8;
@@ -9,31 +16,249 @@ type MemoryArea = RangeInclusive<Address>;
 
 pub struct MemorySpace {
     // 8KB Working RAM
-    // 8KB Video RAM
     work_ram: [u8; 8192],
-    graphic_ram: [u8; 8192],
 
-    // create VideoController struct with oam ram in CPU
-    object_attribute_memory: [u8; 159],
+    // Video RAM and OAM belong to the PPU
+    ppu: Ppu,
 
     cartridge: Box<dyn Cartridge>,
+    // Frames left before the contacts of a freshly inserted cartridge settle, and the lines
+    // not touching yet on each access
+    loose_frames: u64,
+    bus_noise: Cell<Xorshift>,
+
+    cheats: Cheats,
+    // Set by the boot ROM through 0xFF50 as it hands over, the cartridge shows from then on
+    boot_rom_disabled: bool,
+    // Debugging aid letting the CPU into VRAM and OAM whatever the PPU is doing
+    ignore_access_restrictions: bool,
+    recorder: Option<Box<dyn FrameSink>>,
+    // IF, raised by the devices, cleared by the game or the CPU servicing it
+    interrupt_flag: Byte,
+    frame_cycles: u32,
+    frame: u64,
 }
 
 impl MemorySpace {
     pub fn new(cartridge: Box<dyn Cartridge>) -> MemorySpace {
         MemorySpace {
             work_ram: [0; 8192],
-            graphic_ram: [0; 8192],
-            object_attribute_memory: [0; 159],
-            cartridge
+            ppu: Ppu::new(),
+            cartridge,
+            loose_frames: 0,
+            bus_noise: Cell::new(Xorshift::new(0)),
+            cheats: Cheats::default(),
+            boot_rom_disabled: false,
+            ignore_access_restrictions: false,
+            recorder: None,
+            interrupt_flag: 0,
+            frame_cycles: 0,
+            frame: 0,
         }
     }
 
+    pub fn power_on(&mut self, init: &MemoryInit) {
+        init.fill(Region::WorkRam, &mut self.work_ram);
+        init.fill(Region::VideoRam, self.ppu.vram_mut());
+        init.fill(Region::Oam, self.ppu.oam_mut());
+    }
+
     pub fn cartridge_is_mapped(&self) -> bool {
-        self[0xFF50] == 1
+        self.boot_rom_disabled
+    }
+
+    pub fn cartridge(&self) -> &dyn Cartridge {
+        self.cartridge.as_ref()
+    }
+
+    // The CPU keeps running from wherever it was, the caller flushes the save, see save::eject
+    pub fn eject(&mut self) -> Box<dyn Cartridge> {
+        info!("Cartridge ejected");
+        mem::replace(&mut self.cartridge, Box::new(EmptySlot))
+    }
+
+    // The CPU carries on with the new cartridge's mapper registers at their power-on state.
+    // Returns what was in the slot
+    pub fn insert(&mut self, cartridge: Box<dyn Cartridge>) -> Box<dyn Cartridge> {
+        info!("Cartridge {} inserted", cartridge.title().trim_end_matches(char::from(0)));
+        mem::replace(&mut self.cartridge, cartridge)
+    }
+
+    // While a cartridge slides in, data lines whose contacts do not touch yet float high
+    // through their pull-up resistors. Which ones is down to chance on every access, the
+    // seed makes it repeat between runs
+    pub fn loosen_contacts(&mut self, frames: u64, seed: u64) {
+        self.loose_frames = frames;
+        self.bus_noise.set(Xorshift::new(seed));
+    }
+
+    fn floating_lines(&self) -> Byte {
+        if self.loose_frames == 0 {
+            return 0;
+        }
+        let mut noise = self.bus_noise.get();
+        let lines = noise.next_byte();
+        self.bus_noise.set(noise);
+        lines
+    }
+
+    pub fn ignore_access_restrictions(&mut self, ignore: bool) {
+        self.ignore_access_restrictions = ignore;
+    }
+
+    // Blocked reads see 0xFF and blocked writes are dropped
+    fn blocked(&self, address: Address) -> bool {
+        let accessible = match address {
+            0x8000..=0x9FFF => self.ppu.vram_accessible(),
+            0xFE00..=0xFE9F => self.ppu.oam_accessible(),
+            _ => true,
+        };
+        if !accessible && !self.ignore_access_restrictions {
+            trace!("Access to {:#X} blocked by the PPU", address);
+            return true;
+        }
+        false
+    }
+
+    pub fn read(&self, address: Address) -> Byte {
+        match address {
+            _ if self.blocked(address) => 0xFF,
+            // External RAM (Cartridge)
+            0xA000..=0xBFFF => self.cartridge.read(address) | self.floating_lines(),
+            // The boot ROM only covers the first 256 bytes of the cartridge
+            0x0000..=0x00FF if !self.cartridge_is_mapped() => BOOT_ROM[address as usize],
+            // Cartridge
+            0x0000..=0x7FFF => self.cheats.patch_rom(address, self.cartridge.read(address) | self.floating_lines()),
+            INTERRUPT_FLAG => 0xE0 | self.interrupt_flag,
+            ppu::LCDC..=ppu::WX if address != DMA => self.ppu.read_register(address),
+            ppu::VBK | ppu::BCPS..=ppu::OPRI => self.ppu.read_register(address),
+            // Open bus on the registers of devices not emulated yet, 0xFF50 included
+            0xFF00..=0xFF7F => {
+                trace!("Read from unhandled I/O register {:#X}", address);
+                0xFF
+            }
+            0xFEA0..=0xFEFF => 0xFF,
+            // High RAM and IE belong to the CPU, open bus for anything else reaching here
+            0xFF80..=0xFFFF => {
+                trace!("Read from CPU-owned {:#X} reached memory", address);
+                0xFF
+            }
+            _ => self[address],
+        }
+    }
+
+    pub fn write(&mut self, address: Address, data: Byte) {
+        trace!("Writing {:#X} into memory address {:#X}", data, address);
+
+        match address {
+            _ if self.blocked(address) => {}
+            // Cartridge ROM and RAM, the mapper decides what a write means
+            0x0000..=0x7FFF => {
+                self.cartridge.write(address, data | self.floating_lines());
+                trace!("Cartridge banks: {}", self.cartridge.current_banks());
+            }
+            0xA000..=0xBFFF => self.cartridge.write(address, data | self.floating_lines()),
+            INTERRUPT_FLAG => self.interrupt_flag = data & 0x1F,
+            DMA => self.dma(data),
+            ppu::LCDC..=ppu::WX | ppu::VBK | ppu::BCPS..=ppu::OPRI => self.ppu.write_register(address, data),
+            // Only a reset maps the boot ROM back in
+            BOOT_ROM_DISABLE => {
+                if data != 0 && !self.boot_rom_disabled {
+                    debug!("Boot ROM disabled, cartridge mapped");
+                    self.boot_rom_disabled = true;
+                }
+            }
+            0xFF00..=0xFF7F => trace!("Write of {:#X} to unhandled I/O register {:#X} dropped", data, address),
+            0xFEA0..=0xFEFF => {}
+            0xFF80..=0xFFFF => trace!("Write of {:#X} to CPU-owned {:#X} dropped", data, address),
+            _ => self[address] = data,
+        }
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // Every frame the PPU puts out from now on goes into the sink
+    pub fn record(&mut self, sink: Box<dyn FrameSink>) {
+        self.recorder = Some(sink);
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(mut sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Copies a page into OAM, at once rather than over the 160 cycles it really takes. The
+    // DMA unit only reaches the external bus, pages from 0xE0 up read the work RAM below
+    fn dma(&mut self, page: Byte) {
+        trace!("OAM DMA from {:#X}00", page);
+        let page = if page >= 0xE0 { page - 0x20 } else { page };
+        let source = (page as Address) << 8;
+        for offset in 0..ppu::OAM_SIZE as Address {
+            let data = self.read(source + offset);
+            self.ppu.oam_mut()[offset as usize] = data;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.cartridge.tick(cycles);
+        self.ppu.tick(cycles);
+        self.interrupt_flag |= self.ppu.take_interrupts();
+        if self.ppu.take_frame() {
+            self.record_frame();
+        }
+
+        self.frame_cycles += cycles;
+        if self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frame_cycles -= CYCLES_PER_FRAME;
+            self.frame += 1;
+            self.loose_frames = self.loose_frames.saturating_sub(1);
+            self.apply_cheats();
+        }
+    }
+
+    // A failing sink stops the recording, not the emulation
+    fn record_frame(&mut self) {
+        if let Some(sink) = self.recorder.as_mut() {
+            if let Err(e) = sink.frame(&screenshot::rgb(&self.ppu)) {
+                error!("Recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
+    // GameShark codes write their bytes at every frame boundary
+    fn apply_cheats(&mut self) {
+        let ram_bank = self.cartridge.current_banks().ram;
+        let writes: Vec<(Address, Byte)> = self.cheats.frame_writes(ram_bank).collect();
+        for (address, data) in writes {
+            self.write(address, data);
+        }
     }
 }
 
+const INTERRUPT_FLAG: Address = 0xFF0F;
+const DMA: Address = 0xFF46;
+const BOOT_ROM_DISABLE: Address = 0xFF50;
 const MEMORY_START: Address = 0x0000;
 const MEMORY_END: Address = 0xFFFF;
 
@@ -81,7 +306,7 @@ impl ops::Index<Address> for MemorySpace {
                 },
                 // OAM memory
                 0xFE00..=0xFE9F => {
-                    &self.object_attribute_memory[(address - 0xFE00) as usize]
+                    &self.ppu.oam()[(address - 0xFE00) as usize]
                 },
                 // Echo RAM
                 0xE000..=0xFDFF => {
@@ -92,25 +317,12 @@ impl ops::Index<Address> for MemorySpace {
                 0xC000..=0xDFFF => {
                     &self.work_ram[(address - 0xC000) as usize]
                 },
-                // External RAM (Cartridge)
-                0xA000..=0xBFFF => {
-                    // TODO check if cartridge has external RAM available?
-                    &self.cartridge[address]
-                },
                 // Graphics RAM
                 0x8000..=0x9FFF => {
                     // Remember, space is only 16KB although the whole memory map is 64KB
-                    &self.graphic_ram[(address - 0x8000) as usize]
-                },
-                // Cartridge
-                0x0000..=0x7FFF => {
-                    if self.cartridge_is_mapped() {
-                        &self.cartridge[address]
-                    } else {
-                        &BOOT_ROM[address as usize]
-                    }
+                    &self.ppu.vram()[(address - 0x8000) as usize]
                 },
-                _ => panic!("Address {:#X} does not belong to memory space, cannot map to area", address),
+                _ => panic!("Address {:#X} belongs to the cartridge, use MemorySpace::read", address),
             };
 
             data
@@ -124,14 +336,33 @@ impl ops::IndexMut<Address> for MemorySpace {
             panic!("Invalid unsafe memory write to {:#X}", address);
         } else {
             trace!("Writing memory address {:#X}", address);
-            &mut self[address]
+
+            match address {
+                // OAM memory
+                0xFE00..=0xFE9F => {
+                    &mut self.ppu.oam_mut()[(address - 0xFE00) as usize]
+                },
+                // Echo RAM
+                0xE000..=0xFDFF => {
+                    &mut self.work_ram[(address - 0xE000) as usize]
+                },
+                // Work Ram
+                0xC000..=0xDFFF => {
+                    &mut self.work_ram[(address - 0xC000) as usize]
+                },
+                // Graphics RAM
+                0x8000..=0x9FFF => {
+                    &mut self.ppu.vram_mut()[(address - 0x8000) as usize]
+                },
+                _ => panic!("Address {:#X} cannot be written directly, use MemorySpace::write", address),
+            }
         }
     }
 }
 
 impl fmt::Debug for MemorySpace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Memory({:?} bytes)", self.work_ram.len() + self.graphic_ram.len())
+        write!(f, "Memory({:?} bytes)", self.work_ram.len() + self.ppu.vram().len())
     }
 }
 
@@ -154,3 +385,105 @@ const BOOT_ROM: [Byte; 256] = [
     0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13, 0xBE, 0x20, 0xFE, 0x23, 0x7D, 0xFE, 0x34, 0x20,
     0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50,
 ];
+
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+    use crate::cartridge::cartridge::decode_cartridge_as;
+    use crate::cartridge::mapper::Mapper;
+
+    #[test]
+    fn should_block_vram_and_oam_by_ppu_mode() {
+        let mut memory = MemorySpace::new(Box::new(EmptySlot));
+        memory.write(0x8000, 0x12);
+        memory.write(0xFE00, 0x34);
+        memory.write(ppu::LCDC, 0x80);
+
+        // Line 0 after enabling the LCD starts without an OAM scan
+        assert_eq!(memory.read(0xFE00), 0x34);
+        memory.tick(80);
+        assert_eq!(memory.read(0x8000), 0xFF);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+        memory.write(0x8000, 0x56);
+
+        memory.tick(172);
+        assert_eq!(memory.read(0x8000), 0x12);
+        memory.tick(204);
+        assert_eq!(memory.read(0x8000), 0x12);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+
+        memory.ignore_access_restrictions(true);
+        assert_eq!(memory.read(0xFE00), 0x34);
+    }
+
+    #[test]
+    fn should_dma_from_work_ram_for_the_top_pages() {
+        let mut memory = MemorySpace::new(Box::new(EmptySlot));
+        for offset in 0..ppu::OAM_SIZE as Address {
+            memory.write(0xDF00 + offset, offset as Byte);
+        }
+
+        memory.write(DMA, 0xFF);
+
+        assert_eq!(memory.ppu().oam()[..ppu::OAM_SIZE], (0..ppu::OAM_SIZE as Byte).collect::<Vec<_>>()[..]);
+        assert_eq!(memory.read(0xFF80), 0xFF);
+        memory.write(0xFFFF, 0x1F);
+        assert_eq!(memory.read(0xFFFF), 0xFF);
+    }
+
+    #[test]
+    fn should_apply_gameshark_codes_to_their_ram_bank() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x1B;
+        rom[0x149] = 0x03;
+        let mut memory = MemorySpace::new(decode_cartridge_as(rom, Mapper::Mbc5));
+        memory.cheats_mut().add("02551AA0", "").unwrap();
+        memory.write(0x0000, 0x0A);
+
+        memory.tick(CYCLES_PER_FRAME);
+        assert_eq!(memory.read(0xA01A), 0x00);
+
+        memory.write(0x4000, 0x02);
+        memory.tick(CYCLES_PER_FRAME);
+        assert_eq!(memory.read(0xA01A), 0x55);
+        memory.write(0x4000, 0x00);
+        assert_eq!(memory.read(0xA01A), 0x00);
+    }
+
+    #[test]
+    fn should_latch_boot_rom_off_and_float_unhandled_io() {
+        let mut memory = MemorySpace::new(Box::new(EmptySlot));
+        assert_eq!(memory.read(0x0000), BOOT_ROM[0]);
+        assert_eq!(memory.read(0x0100), 0xFF);
+        assert_eq!(memory.read(0xFF01), 0xFF);
+        memory.write(0xFF01, 0x12);
+        memory.write(0xFEA0, 0x34);
+
+        memory.write(BOOT_ROM_DISABLE, 0x01);
+        memory.write(BOOT_ROM_DISABLE, 0x00);
+        assert!(memory.cartridge_is_mapped());
+        assert_eq!(memory.read(0x0000), 0xFF);
+        assert_eq!(memory.read(BOOT_ROM_DISABLE), 0xFF);
+    }
+
+    #[test]
+    fn should_float_data_lines_until_contacts_settle() {
+        let reads = |seed: u64| {
+            let mut memory = MemorySpace::new(decode_cartridge_as(vec![0; 0x8000], Mapper::RomOnly));
+            memory.write(BOOT_ROM_DISABLE, 0x01);
+            memory.loosen_contacts(2, seed);
+            let loose: Vec<Byte> = (0x0100..0x0140).map(|address| memory.read(address)).collect();
+
+            memory.tick(CYCLES_PER_FRAME);
+            assert!((0x0100..0x0140).any(|address| memory.read(address) != 0x00));
+            memory.tick(CYCLES_PER_FRAME);
+            assert!((0x0100..0x0140).all(|address| memory.read(address) == 0x00));
+            loose
+        };
+
+        let loose = reads(7);
+        assert!(loose.iter().any(|&byte| byte != 0x00));
+        assert_eq!(loose, reads(7));
+        assert_ne!(loose, reads(8));
+    }
+}
@@ -0,0 +1,187 @@
+use std::str::FromStr;
+use crate::cartridge::cartridge::Cartridge;
+
+// RAM comes up holding whatever its cells settled on. Games reading it before writing
+// behave differently from one unit to the next, zeroing it all hides those bugs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InitMode {
+    Zero,
+    Ones,
+    Random,
+    // What dumps of each model tend to show, see model_byte
+    Dmg,
+    Cgb,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    WorkRam,
+    HighRam,
+    VideoRam,
+    Oam,
+    CartridgeRam,
+}
+
+// One --memory-init value, a mode for every region or for one of them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InitSetting {
+    pub region: Option<Region>,
+    pub mode: InitMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryInit {
+    modes: [InitMode; 5],
+    seed: u64,
+}
+
+impl MemoryInit {
+    // Later settings win, so a blanket mode can be refined region by region
+    pub fn new(settings: &[InitSetting], seed: u64) -> MemoryInit {
+        let mut modes = [InitMode::Zero; 5];
+        for setting in settings {
+            match setting.region {
+                Some(region) => modes[region as usize] = setting.mode,
+                None => modes = [setting.mode; 5],
+            }
+        }
+        MemoryInit { modes, seed }
+    }
+
+    pub fn mode(&self, region: Region) -> InitMode {
+        self.modes[region as usize]
+    }
+
+    // Every region draws from its own generator, the order they are filled in does not matter
+    pub fn fill(&self, region: Region, memory: &mut [u8]) {
+        let mut random = Xorshift::new(self.seed ^ (region as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mode = self.mode(region);
+
+        for (offset, byte) in memory.iter_mut().enumerate() {
+            *byte = match mode {
+                InitMode::Zero => 0x00,
+                InitMode::Ones => 0xFF,
+                InitMode::Random => random.next_byte(),
+                InitMode::Dmg | InitMode::Cgb => model_byte(mode, region, offset, &mut random),
+            };
+        }
+    }
+
+    // Done before the battery save is loaded over it
+    pub fn fill_cartridge(&self, cartridge: &mut dyn Cartridge) {
+        if let Some(size) = cartridge.save_ram().map(<[u8]>::len) {
+            let mut ram = vec![0; size];
+            self.fill(Region::CartridgeRam, &mut ram);
+            cartridge.load_ram(&ram);
+        }
+    }
+}
+
+// The boot ROM clears VRAM on both models. CGB work RAM shows runs of 0x00 and 0xFF, DMG
+// static RAM and everything else is noise
+fn model_byte(mode: InitMode, region: Region, offset: usize, random: &mut Xorshift) -> u8 {
+    match (mode, region) {
+        (_, Region::VideoRam) => 0x00,
+        (InitMode::Cgb, Region::WorkRam) => if offset & 0x08 == 0 { 0x00 } else { 0xFF },
+        (InitMode::Cgb, Region::Oam) => 0x00,
+        _ => random.next_byte(),
+    }
+}
+
+// Small and seedable, the same seed gives the same memory on every host
+#[derive(Clone, Copy)]
+pub(crate) struct Xorshift(u64);
+
+impl Xorshift {
+    pub(crate) fn new(seed: u64) -> Xorshift {
+        // The state must never be zero
+        Xorshift(seed | 1)
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u8
+    }
+}
+
+impl FromStr for InitMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.to_ascii_lowercase().as_str() {
+            "zero" => Ok(InitMode::Zero),
+            "ff" => Ok(InitMode::Ones),
+            "random" => Ok(InitMode::Random),
+            "dmg" => Ok(InitMode::Dmg),
+            "cgb" => Ok(InitMode::Cgb),
+            _ => Err(format!("Unknown init mode {}, expected zero, ff, random, dmg or cgb", mode)),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(region: &str) -> Result<Self, Self::Err> {
+        match region.to_ascii_lowercase().as_str() {
+            "wram" => Ok(Region::WorkRam),
+            "hram" => Ok(Region::HighRam),
+            "vram" => Ok(Region::VideoRam),
+            "oam" => Ok(Region::Oam),
+            "sram" => Ok(Region::CartridgeRam),
+            _ => Err(format!("Unknown region {}, expected wram, hram, vram, oam or sram", region)),
+        }
+    }
+}
+
+// Either random, or wram=random to set a single region
+impl FromStr for InitSetting {
+    type Err = String;
+
+    fn from_str(setting: &str) -> Result<Self, Self::Err> {
+        match setting.split_once('=') {
+            Some((region, mode)) => Ok(InitSetting { region: Some(region.parse()?), mode: mode.parse()? }),
+            None => Ok(InitSetting { region: None, mode: setting.parse()? }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod power_on_tests {
+    use super::*;
+
+    #[test]
+    fn should_refine_blanket_mode_per_region() {
+        let settings: Vec<InitSetting> = ["random", "oam=ff"].iter().map(|s| s.parse().unwrap()).collect();
+        let init = MemoryInit::new(&settings, 7);
+
+        assert_eq!(init.mode(Region::WorkRam), InitMode::Random);
+        assert_eq!(init.mode(Region::Oam), InitMode::Ones);
+        assert!("wram=maybe".parse::<InitSetting>().is_err());
+    }
+
+    #[test]
+    fn should_repeat_random_contents_for_a_seed() {
+        let init = MemoryInit::new(&["random".parse().unwrap()], 42);
+        let (mut first, mut second, mut other) = ([0; 64], [0; 64], [0; 64]);
+
+        init.fill(Region::WorkRam, &mut first);
+        init.fill(Region::WorkRam, &mut second);
+        init.fill(Region::HighRam, &mut other);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn should_stripe_cgb_work_ram() {
+        let init = MemoryInit::new(&["cgb".parse().unwrap()], 0);
+        let mut ram = [0x55; 32];
+        init.fill(Region::WorkRam, &mut ram);
+
+        assert_eq!(ram[..8], [0x00; 8]);
+        assert_eq!(ram[8..16], [0xFF; 8]);
+    }
+}
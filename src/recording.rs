@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use log::info;
+use crate::soc::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// The LCD refreshes every 70224 cycles of the 4194304 Hz clock, about 59.73 times a second
+const CLOCK_RATE: u64 = 4_194_304;
+const FRAME_CYCLES: u64 = 70_224;
+
+// GIF delays are in hundredths of a second and players slow anything under 2 down to 10,
+// frames shown for less are dropped
+const MIN_GIF_DELAY: u64 = 2;
+const MAX_LZW_CODES: u16 = 4096;
+
+// Anything able to take the frames of a headless run
+pub trait FrameSink {
+    // SCREEN_WIDTH * SCREEN_HEIGHT RGB triplets in row order, one per refresh
+    fn frame(&mut self, rgb: &[u8]) -> io::Result<()>;
+
+    // Flushes whatever is buffered, no frame comes after
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+// Picks the format from the extension: y4m, gif, or rgb for headerless RGB24 frames
+pub fn open(path: &Path) -> io::Result<Box<dyn FrameSink>> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    let output = BufWriter::new(File::create(path)?);
+    let sink: Box<dyn FrameSink> = match extension.to_ascii_lowercase().as_str() {
+        "y4m" => Box::new(Y4mSink::new(output)?),
+        "gif" => Box::new(GifSink::new(output)?),
+        "rgb" | "raw" => Box::new(RawSink { output }),
+        _ => {
+            let message = format!("Unknown recording format {}, expected y4m, gif or rgb", path.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    };
+    info!("Recording frames into {}", path.display());
+    Ok(sink)
+}
+
+pub struct RawSink<W: Write> {
+    output: W,
+}
+
+impl<W: Write> FrameSink for RawSink<W> {
+    fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        self.output.write_all(rgb)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// https://wiki.multimedia.cx/index.php/YUV4MPEG2
+// Full resolution 4:4:4 planes, subsampling would smear single pixels
+pub struct Y4mSink<W: Write> {
+    output: W,
+}
+
+impl<W: Write> Y4mSink<W> {
+    pub fn new(mut output: W) -> io::Result<Y4mSink<W>> {
+        writeln!(output, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", SCREEN_WIDTH, SCREEN_HEIGHT, CLOCK_RATE, FRAME_CYCLES)?;
+        Ok(Y4mSink { output })
+    }
+}
+
+impl<W: Write> FrameSink for Y4mSink<W> {
+    fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let pixels = SCREEN_WIDTH * SCREEN_HEIGHT;
+        let mut planes = vec![0; pixels * 3];
+        for (pixel, color) in rgb.chunks(3).enumerate() {
+            let [y, cb, cr] = to_ycbcr(color[0], color[1], color[2]);
+            planes[pixel] = y;
+            planes[pixels + pixel] = cb;
+            planes[2 * pixels + pixel] = cr;
+        }
+        self.output.write_all(b"FRAME\n")?;
+        self.output.write_all(&planes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// BT.601 in studio range, what players assume for Y4M without a color range
+fn to_ycbcr(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = 16 + ((66 * r + 129 * g + 25 * b + 128) >> 8);
+    let cb = 128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8);
+    let cr = 128 + ((112 * r - 94 * g - 18 * b + 128) >> 8);
+    [y as u8, cb as u8, cr as u8]
+}
+
+// https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+// A frame is held back until the next different one shows up, repeated frames only
+// lengthen its delay
+pub struct GifSink<W: Write> {
+    output: W,
+    pending: Option<Vec<u8>>,
+    // Frames received so far, and the time in hundredths of a second written so far
+    frames: u64,
+    written: u64,
+}
+
+impl<W: Write> GifSink<W> {
+    pub fn new(mut output: W) -> io::Result<GifSink<W>> {
+        output.write_all(b"GIF89a")?;
+        output.write_all(&(SCREEN_WIDTH as u16).to_le_bytes())?;
+        output.write_all(&(SCREEN_HEIGHT as u16).to_le_bytes())?;
+        // No global color table, every frame brings its own
+        output.write_all(&[0x00, 0x00, 0x00])?;
+        // Loops forever
+        output.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(GifSink { output, pending: None, frames: 0, written: 0 })
+    }
+
+    fn elapsed(&self) -> u64 {
+        (self.frames * FRAME_CYCLES * 100 + CLOCK_RATE / 2) / CLOCK_RATE
+    }
+
+    fn write_pending(&mut self, delay: u64) -> io::Result<()> {
+        if let Some(frame) = self.pending.take() {
+            write_gif_frame(&mut self.output, &frame, delay.min(u16::MAX as u64) as u16)?;
+            self.written += delay;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> FrameSink for GifSink<W> {
+    fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        if self.pending.as_deref() != Some(rgb) {
+            let delay = self.elapsed() - self.written;
+            if delay >= MIN_GIF_DELAY {
+                self.write_pending(delay)?;
+            }
+            self.pending = Some(rgb.to_vec());
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let delay = (self.elapsed() - self.written).max(MIN_GIF_DELAY);
+        self.write_pending(delay)?;
+        self.output.write_all(&[0x3B])?;
+        self.output.flush()
+    }
+}
+
+fn write_gif_frame(output: &mut impl Write, rgb: &[u8], delay: u16) -> io::Result<()> {
+    let (palette, indices) = index_colors(rgb);
+    // Color tables hold a power of two colors, 2 at least
+    let bits = (usize::BITS - (palette.len() - 1).max(1).leading_zeros()) as u8;
+
+    // Graphic control extension, the frame is left in place for the next one
+    output.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+    output.write_all(&delay.to_le_bytes())?;
+    output.write_all(&[0x00, 0x00])?;
+
+    output.write_all(&[0x2C, 0x00, 0x00, 0x00, 0x00])?;
+    output.write_all(&(SCREEN_WIDTH as u16).to_le_bytes())?;
+    output.write_all(&(SCREEN_HEIGHT as u16).to_le_bytes())?;
+    output.write_all(&[0x80 | (bits - 1)])?;
+    let mut table: Vec<u8> = palette.iter().flatten().copied().collect();
+    table.resize(3 << bits, 0);
+    output.write_all(&table)?;
+
+    let code_size = bits.max(2);
+    output.write_all(&[code_size])?;
+    for block in lzw(&indices, code_size).chunks(255) {
+        output.write_all(&[block.len() as u8])?;
+        output.write_all(block)?;
+    }
+    output.write_all(&[0x00])
+}
+
+// Up to 256 colors as they are, CGB frames with more fall back to 3-3-2 RGB
+fn index_colors(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+
+    for pixel in rgb.chunks(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = *lookup.entry(color).or_insert_with(|| {
+            palette.push(color);
+            palette.len() - 1
+        });
+        if index > u8::MAX as usize {
+            let reduced: Vec<u8> = rgb.chunks(3)
+                .flat_map(|pixel| [pixel[0] & 0xE0, pixel[1] & 0xE0, pixel[2] & 0xC0])
+                .collect();
+            return index_colors(&reduced);
+        }
+        indices.push(index as u8);
+    }
+    (palette, indices)
+}
+
+// Variable length codes, packed from the least significant bit. The table starts over
+// once it holds 4096 codes
+fn lzw(indices: &[u8], code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter::default();
+    let mut bits = code_size + 1;
+    let mut next = end + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+
+    writer.write(clear, bits);
+    let mut prefix = indices[0] as u16;
+    for &index in &indices[1..] {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, bits);
+        if next == MAX_LZW_CODES {
+            writer.write(clear, bits);
+            table.clear();
+            bits = code_size + 1;
+            next = end + 1;
+        } else {
+            table.insert((prefix, index), next);
+            next += 1;
+            // Decoders grow their codes one code late, once they add the entry themselves
+            if next > 1 << bits && bits < 12 {
+                bits += 1;
+            }
+        }
+        prefix = index as u16;
+    }
+    writer.write(prefix, bits);
+    writer.write(end, bits);
+    writer.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    length: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, bits: u8) {
+        self.buffer |= (code as u32) << self.length;
+        self.length += bits;
+        while self.length >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.length -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.length > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod recording_tests {
+    use super::*;
+
+    fn frame(color: u8) -> Vec<u8> {
+        vec![color; SCREEN_WIDTH * SCREEN_HEIGHT * 3]
+    }
+
+    #[test]
+    fn should_write_y4m_planes() {
+        let mut output = Vec::new();
+        let mut sink = Y4mSink::new(&mut output).unwrap();
+        sink.frame(&frame(0xFF)).unwrap();
+        sink.frame(&frame(0x00)).unwrap();
+        sink.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\n";
+        let size = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+        assert!(output.starts_with(header));
+        assert_eq!(output.len(), header.len() + 2 * (6 + size));
+        assert_eq!(output[header.len() + 6], 235);
+        assert_eq!(output[header.len() + 6 + size - 1], 128);
+        assert_eq!(output[header.len() + 2 * 6 + size], 16);
+    }
+
+    #[test]
+    fn should_convert_to_studio_range() {
+        assert_eq!(to_ycbcr(0xFF, 0xFF, 0xFF), [235, 128, 128]);
+        assert_eq!(to_ycbcr(0x00, 0x00, 0x00), [16, 128, 128]);
+        assert_eq!(to_ycbcr(0xFF, 0x00, 0x00), [82, 90, 240]);
+    }
+
+    #[test]
+    fn should_merge_repeated_gif_frames() {
+        let mut output = Vec::new();
+        let mut sink = GifSink::new(&mut output).unwrap();
+        for color in [0x00, 0x00, 0x00, 0xFF, 0x55, 0x55] {
+            sink.frame(&frame(color)).unwrap();
+        }
+        sink.finish().unwrap();
+
+        // 3 frames last 5 hundredths, then 1 frame and 2 frames round up to 2 and 3
+        let delays: Vec<u16> = output.windows(4)
+            .enumerate()
+            .filter(|(_, window)| window == &[0x21, 0xF9, 0x04, 0x04])
+            .map(|(offset, _)| u16::from_le_bytes([output[offset + 4], output[offset + 5]]))
+            .collect();
+        assert_eq!(delays, vec![5, 2, 3]);
+        assert_eq!(output.last(), Some(&0x3B));
+    }
+
+    #[test]
+    fn should_drop_frames_shorter_than_gif_delays() {
+        let mut output = Vec::new();
+        let mut sink = GifSink::new(&mut output).unwrap();
+        sink.frames = 1;
+        sink.written = 2;
+        sink.frame(&frame(0x00)).unwrap();
+        sink.frame(&frame(0xFF)).unwrap();
+        assert_eq!(sink.pending, Some(frame(0xFF)));
+        assert_eq!(sink.written, 2);
+    }
+
+    // Plain GIF decoder, growing codes once its table is full for the current size
+    fn unlzw(data: &[u8], code_size: u8) -> Vec<u8> {
+        let clear = 1usize << code_size;
+        let reset = || -> Vec<Vec<u8>> { (0..clear + 2).map(|code| vec![code as u8]).collect() };
+        let mut table = reset();
+        let mut bits = code_size + 1;
+        let mut position = 0;
+        let mut previous: Option<usize> = None;
+        let mut output = Vec::new();
+
+        loop {
+            let code = (0..bits as usize)
+                .map(|bit| ((data[(position + bit) / 8] >> ((position + bit) % 8)) & 0x01) as usize)
+                .enumerate()
+                .fold(0, |code, (bit, value)| code | value << bit);
+            position += bits as usize;
+
+            if code == clear {
+                table = reset();
+                bits = code_size + 1;
+                previous = None;
+                continue;
+            } else if code == clear + 1 {
+                return output;
+            }
+
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let mut entry = table[previous.unwrap()].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+            };
+            if let Some(previous) = previous {
+                if table.len() < MAX_LZW_CODES as usize {
+                    let mut added = table[previous].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                }
+            }
+            output.extend_from_slice(&entry);
+            previous = Some(code);
+            if table.len() == 1 << bits && bits < 12 {
+                bits += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn should_round_trip_lzw_through_table_resets() {
+        let noise: Vec<u8> = (0..40_000u32).map(|index| (index * 7919 % 251) as u8).collect();
+        assert_eq!(unlzw(&lzw(&noise, 8), 8), noise);
+
+        let shades: Vec<u8> = (0..SCREEN_WIDTH * SCREEN_HEIGHT).map(|pixel| (pixel / 7 % 4) as u8).collect();
+        assert_eq!(unlzw(&lzw(&shades, 2), 2), shades);
+    }
+
+    #[test]
+    fn should_reduce_colors_past_256() {
+        let rgb: Vec<u8> = (0..300u32).flat_map(|color| [color as u8, (color >> 8) as u8, 0]).collect();
+        let (palette, indices) = index_colors(&rgb);
+        assert!(palette.len() <= 256);
+        assert_eq!(palette[indices[299] as usize], [0x20, 0x00, 0x00]);
+    }
+}
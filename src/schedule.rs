@@ -0,0 +1,98 @@
+use crate::configuration::Config;
+use std::path::PathBuf;
+
+// Frame-indexed options, carried out as their frame starts
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    Screenshot(PathBuf),
+    VramDump(PathBuf),
+    Eject,
+    Insert(PathBuf),
+}
+
+// Actions in frame order whatever order the options were given in
+pub struct Schedule {
+    actions: Vec<(u64, Action)>,
+}
+
+impl Schedule {
+    // Actions on the same frame keep the order they are given in
+    pub fn new(mut actions: Vec<(u64, Action)>) -> Schedule {
+        actions.sort_by_key(|&(frame, _)| frame);
+        Schedule { actions }
+    }
+
+    // A screenshot or VRAM dump on the swap frame still shows the outgoing game. The slot
+    // stays empty for --swap-gap frames
+    pub fn from_config(config: &Config) -> Schedule {
+        let mut actions = Vec::new();
+        if let Some(path) = &config.screenshot {
+            actions.push((config.screenshot_frame, Action::Screenshot(path.clone())));
+        }
+        if let Some(path) = &config.vram_dump {
+            actions.push((config.vram_dump_frame, Action::VramDump(path.clone())));
+        }
+        if let Some(path) = &config.swap {
+            actions.push((config.swap_frame, Action::Eject));
+            actions.push((config.swap_frame + config.swap_gap, Action::Insert(path.clone())));
+        }
+        Schedule::new(actions)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    // Takes out the actions due by the given frame
+    pub fn due(&mut self, frame: u64) -> Vec<Action> {
+        let count = self.actions.iter().take_while(|&&(at, _)| at <= frame).count();
+        self.actions.drain(..count).map(|(_, action)| action).collect()
+    }
+}
+
+#[cfg(test)]
+mod schedule_tests {
+    use super::*;
+
+    fn screenshot() -> Action {
+        Action::Screenshot(PathBuf::from("shot.png"))
+    }
+
+    fn vram_dump() -> Action {
+        Action::VramDump(PathBuf::from("vram"))
+    }
+
+    fn insert() -> Action {
+        Action::Insert(PathBuf::from("other.gb"))
+    }
+
+    #[test]
+    fn should_run_actions_in_frame_order() {
+        let mut schedule = Schedule::new(vec![(90, screenshot()), (30, Action::Eject), (35, insert())]);
+
+        assert_eq!(schedule.due(29), vec![]);
+        assert_eq!(schedule.due(30), vec![Action::Eject]);
+        assert_eq!(schedule.due(34), vec![]);
+        assert_eq!(schedule.due(35), vec![insert()]);
+        assert_eq!(schedule.due(89), vec![]);
+        assert_eq!(schedule.due(90), vec![screenshot()]);
+    }
+
+    #[test]
+    fn should_dump_vram_before_a_later_screenshot() {
+        let mut schedule = Schedule::new(vec![(90, screenshot()), (10, vram_dump())]);
+
+        assert_eq!(schedule.due(10), vec![vram_dump()]);
+        assert_eq!(schedule.due(90), vec![screenshot()]);
+    }
+
+    #[test]
+    fn should_keep_order_on_the_same_frame_and_catch_up_late_actions() {
+        let actions = vec![(60, screenshot()), (60, vram_dump()), (60, Action::Eject), (60, insert())];
+        let mut schedule = Schedule::new(actions);
+
+        assert_eq!(schedule.due(75), vec![screenshot(), vram_dump(), Action::Eject, insert()]);
+        assert_eq!(schedule.due(75), vec![]);
+        assert!(schedule.is_empty());
+    }
+}
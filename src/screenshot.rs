@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use log::info;
+use crate::soc::ppu::{to_rgb, Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// The current frame as 8 bit RGB triplets in row order
+pub fn rgb(ppu: &Ppu) -> Vec<u8> {
+    ppu.framebuffer().iter()
+        .flat_map(|&color| to_rgb(color, ppu.cgb()))
+        .collect()
+}
+
+// Scaled up with nearest neighbour, pixels stay sharp
+pub fn encode(ppu: &Ppu, scale: usize, output: impl Write) -> io::Result<()> {
+    if scale == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Screenshot scale must be at least 1"));
+    }
+
+    let frame = rgb(ppu);
+    let mut data = Vec::with_capacity(frame.len() * scale * scale);
+    for line in frame.chunks(SCREEN_WIDTH * 3) {
+        let scaled: Vec<u8> = line.chunks(3)
+            .flat_map(|pixel| pixel.repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&scaled);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(output, (SCREEN_WIDTH * scale) as u32, (SCREEN_HEIGHT * scale) as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+pub fn save(ppu: &Ppu, scale: usize, path: &Path) -> io::Result<()> {
+    encode(ppu, scale, BufWriter::new(File::create(path)?))?;
+    info!("Screenshot saved into {}", path.display());
+    Ok(())
+}
+
+// One message each time Enter is pressed in the terminal, read on its own thread so the
+// emulation never waits on it
+pub fn enter_presses() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for _ in io::stdin().lock().lines() {
+            if sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+    use crate::soc::ppu::{BCPD, BCPS, BGP, LCDC};
+
+    fn pixel(png: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let (info, mut reader) = png::Decoder::new(png).read_info().unwrap();
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        let offset = (y * info.width as usize + x) * 3;
+        [data[offset], data[offset + 1], data[offset + 2]]
+    }
+
+    // Solid color 3 tile in the top left corner, pure red as color 3 of CGB palette 0
+    fn drawn(cgb: bool) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_cgb(cgb);
+        ppu.vram_mut()[16..32].copy_from_slice(&[0xFF; 16]);
+        ppu.vram_mut()[0x1800] = 1;
+        ppu.write_register(BGP, 0xE4);
+        ppu.write_register(BCPS, 0x86);
+        ppu.write_register(BCPD, 0x1F);
+        ppu.write_register(BCPD, 0x00);
+        ppu.write_register(LCDC, 0x91);
+        // Past the blank frame following LCD enable
+        for _ in 0..2 * 154 * 456 / 4 {
+            ppu.tick(4);
+        }
+        ppu
+    }
+
+    #[test]
+    fn should_encode_scaled_png() {
+        let mut png = Vec::new();
+        encode(&Ppu::new(), 3, &mut png).unwrap();
+
+        let (info, mut reader) = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!((info.width, info.height), (480, 432));
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        assert!(data.iter().all(|&byte| byte == 0xFF));
+
+        assert!(encode(&Ppu::new(), 0, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn should_encode_drawn_frame_in_dmg_shades() {
+        let mut png = Vec::new();
+        encode(&drawn(false), 2, &mut png).unwrap();
+
+        assert_eq!(pixel(&png, 0, 0), [0x00; 3]);
+        assert_eq!(pixel(&png, 15, 15), [0x00; 3]);
+        assert_eq!(pixel(&png, 16, 0), [0xFF; 3]);
+    }
+
+    #[test]
+    fn should_encode_drawn_frame_in_cgb_colors() {
+        let mut png = Vec::new();
+        encode(&drawn(true), 2, &mut png).unwrap();
+
+        assert_eq!(pixel(&png, 0, 0), [0xFF, 0x00, 0x00]);
+        assert_eq!(pixel(&png, 15, 15), [0xFF, 0x00, 0x00]);
+        assert_eq!(pixel(&png, 16, 0), [0xFF; 3]);
+    }
+}
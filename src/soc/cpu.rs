@@ -1,4 +1,6 @@
 use crate::memory::MemorySpace;
+use crate::power_on::{MemoryInit, Region};
+use crate::soc::ppu::OamCorruption;
 use crate::soc::instruction::{Instruction, Instruction::*, Operand, Operand::*};
 use crate::soc::register::{Flags, MathOps, Registers};
 use crate::utils::{as_u16, hilo};
@@ -16,7 +18,7 @@ pub struct CPU {
     pub halted: bool,
     pub stopped: bool,
 
-    pub high_ram: [u8; 126],
+    pub high_ram: [u8; 127],
 }
 
 impl CPU {
@@ -27,25 +29,39 @@ impl CPU {
             cycle: 0,
             halted: false,
             stopped: false,
-            high_ram: [0; 126]
+            high_ram: [0; 127]
         };
         debug!("CPU initialized");
         cpu
     }
 
+    pub fn power_on(&mut self, init: &MemoryInit) {
+        init.fill(Region::HighRam, &mut self.high_ram);
+        self.memory.power_on(init);
+    }
+
     pub fn run(&mut self) {
         debug!("Fetch-Decode-Execute loop starting");
-        loop {
-            let opcode = self.fetch();
-            let instruction = self.decode(opcode);
-            self.execute(instruction);
+        while !self.halted {
+            self.step();
+        }
+    }
 
-            if self.halted {
-                break;
-            }
+    // Runs until the given frame starts, or the CPU halts
+    pub fn run_until_frame(&mut self, frame: u64) {
+        while !self.halted && self.memory.frame() < frame {
+            self.step();
         }
     }
 
+    fn step(&mut self) {
+        let cycle = self.cycle;
+        let opcode = self.fetch();
+        let instruction = self.decode(opcode);
+        self.execute(instruction);
+        self.memory.tick(self.cycle.wrapping_sub(cycle));
+    }
+
     fn fetch(&mut self) -> OpCode {
         trace!("Fetching next opcode. PC: {:#?}", self.register.PC);
         self.read(Word)
@@ -101,9 +117,14 @@ impl CPU {
             }
             PUSH(op) => {
                 let data: u16 = self.read(op);
+                self.oam_bug(self.register.SP, OamCorruption::Write);
+                self.oam_bug(self.register.SP.wrapping_sub(1), OamCorruption::Write);
                 self.push(data);
             }
             POP(op) => {
+                self.oam_bug(self.register.SP, OamCorruption::ReadIncrease);
+                // The second read is not followed by another increment
+                self.oam_bug(self.register.SP.wrapping_add(1), OamCorruption::Read);
                 let data: u16 = self.pop();
                 self.write(op, data)
             }
@@ -178,6 +199,7 @@ impl CPU {
             }
             INC16(op) => {
                 let n: u16 = self.read(op.clone());
+                self.oam_bug(n, OamCorruption::Write);
                 let result = self.register.carrying_add(n, 1);
                 self.write(op, result);
             }
@@ -188,6 +210,7 @@ impl CPU {
             }
             DEC16(op) => {
                 let n: u16 = self.read(op.clone());
+                self.oam_bug(n, OamCorruption::Write);
                 let result = self.register.borrowing_sub(n, 1);
                 self.write(op, result);
             }
@@ -473,6 +496,12 @@ impl CPU {
         }
     }
 
+    // Reports an address put on the bus by a 16 bit increment or a stack access, the PPU
+    // decides whether it hits OAM during its scan
+    fn oam_bug(&mut self, address: u16, corruption: OamCorruption) {
+        self.memory.ppu_mut().corrupt_oam(address, corruption);
+    }
+
     fn jump_allowed(&self, operand: Operand) -> bool {
         match operand {
             Zero => self.register.read_flag(Flags::Zero),
@@ -532,12 +561,12 @@ impl ReadWrite<u8> for CPU {
                     0xFFFF => self.register.IR,
                     _ => {
                         self.cycle += 4;
-                        self.memory[address + offset]
+                        self.memory.read(address)
                     }
                 }
             }
             Word => {
-                let data = self.memory[self.register.PC];
+                let data = self.memory.read(self.register.PC);
                 self.register.PC += 1;
                 self.cycle += 4;
                 data
@@ -572,7 +601,7 @@ impl ReadWrite<u8> for CPU {
                     0xFFFF => self.register.IR = data,
                     _ => {
                         self.cycle += 4;
-                        self.memory[address + offset] = data
+                        self.memory.write(address, data)
                     }
                 }
             }
@@ -597,7 +626,12 @@ impl ReadWrite<u16> for CPU {
             SP => self.register.SP,
             PC => self.register.PC,
             Word => as_u16(0, self.read(operand)),
-            DWord => as_u16(self.read(Word), self.read(Word)),
+            // Little endian, the low byte comes first
+            DWord => {
+                let low: u8 = self.read(Word);
+                let high: u8 = self.read(Word);
+                as_u16(high, low)
+            }
             _ => panic!("Invalid operand {:?} to read double word", operand),
         };
 
@@ -649,6 +683,27 @@ impl PushPop<u16> for CPU {
 #[cfg(test)]
 mod cpu_tests {
     use super::*;
+    use crate::cartridge::rom::RomOnly;
+
+    #[test]
+    fn should_run_rom_for_a_few_frames() {
+        let mut rom = vec![0; 0x8000];
+        // LD H,0xFF; LD L,0x40; LD (HL),0x91; JP 0x0106
+        rom[0x100..0x109].copy_from_slice(&[0x26, 0xFF, 0x2E, 0x40, 0x36, 0x91, 0xC3, 0x06, 0x01]);
+        let mut memory = MemorySpace::new(Box::new(RomOnly::new(rom)));
+        // Where the boot ROM leaves things
+        memory.write(0xFF50, 0x01);
+        let mut cpu = CPU::new(memory);
+        cpu.register.PC = 0x100;
+
+        cpu.run_until_frame(3);
+
+        assert_eq!(cpu.memory.frame(), 3);
+        assert_eq!(cpu.memory.read(0xFF40), 0x91);
+        assert_eq!(cpu.memory.read(0xFF0F) & 0x01, 0x01);
+        assert_eq!(cpu.memory.read(0xFF50), 0xFF);
+        assert_eq!(cpu.memory.read(0x0100), 0x26);
+    }
 
     // #[test]
     // fn should_fetch_opcode() {
@@ -1,3 +1,4 @@
 pub mod cpu;
 pub mod instruction;
-pub mod register;
\ No newline at end of file
+pub mod register;
+pub mod ppu;
\ No newline at end of file
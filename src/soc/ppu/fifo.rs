@@ -0,0 +1,480 @@
+use std::collections::VecDeque;
+use super::*;
+
+// https://gbdev.io/pandocs/pixel_fifo.html
+// Mode 3 advances one dot at a time: the fetcher fills the background FIFO a tile at a
+// time while one pixel leaves it per dot. Registers are read when the hardware reads
+// them, which is what mid-line raster effects rely on
+const STEP_DOTS: u8 = 2;
+// Sprite fetches stall the background for at least this long
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone, Copy)]
+struct BackgroundPixel {
+    color: u8,
+    attributes: u8,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SpritePixel {
+    color: u8,
+    attributes: u8,
+    index: u8,
+}
+
+pub(super) struct PixelFifo {
+    background: VecDeque<BackgroundPixel>,
+    sprites: VecDeque<SpritePixel>,
+    step: Step,
+    step_dots: u8,
+    // Tile column the fetcher is at, relative to the background scroll or the window
+    fetch_x: u8,
+    tile: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+    // Next pixel to leave the FIFO
+    x: u8,
+    // Fine scroll pixels dropped at the start of the line
+    discard: u8,
+    // The first fetch of every line is thrown away
+    warming_up: bool,
+    in_window: bool,
+    // Sprites of the line already fetched, as a mask over Ppu::sprites
+    fetched: u64,
+    stall: u8,
+    pending_sprite: Option<usize>,
+    // Length of mode 3 so far
+    pub(super) dots: u32,
+}
+
+impl PixelFifo {
+    pub(super) fn new() -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            step: Step::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            tile: 0,
+            attributes: 0,
+            low: 0,
+            high: 0,
+            x: 0,
+            discard: 0,
+            warming_up: true,
+            in_window: false,
+            fetched: 0,
+            stall: 0,
+            pending_sprite: None,
+            dots: 0,
+        }
+    }
+
+    fn restart_fetch(&mut self) {
+        self.step = Step::Tile;
+        self.step_dots = 0;
+        self.fetch_x = 0;
+    }
+
+}
+
+impl Ppu {
+    pub(super) fn start_fifo_line(&mut self) {
+        self.fifo = PixelFifo::new();
+        self.fifo.discard = self.scx % 8;
+    }
+
+    // Runs mode 3 for as many dots as are available, true once the line is complete
+    pub(super) fn draw_dots(&mut self) -> bool {
+        while self.line_cycles > 0 {
+            self.line_cycles -= 1;
+            self.fifo.dots += 1;
+            if self.fifo_dot() {
+                self.drawing_cycles = self.fifo.dots;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn fifo_dot(&mut self) -> bool {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            if self.fifo.stall == 0 {
+                if let Some(index) = self.fifo.pending_sprite.take() {
+                    self.fetch_sprite(index);
+                }
+            }
+            return false;
+        }
+
+        self.start_window();
+
+        while let Some(index) = self.sprite_at_x() {
+            self.fifo.fetched |= 1 << index;
+            // Sprites over the hardware limit come for free
+            if self.sprites[index].extra {
+                self.fetch_sprite(index);
+                continue;
+            }
+            self.fifo.pending_sprite = Some(index);
+            // This dot is the first one of the stall
+            self.fifo.stall = SPRITE_FETCH_DOTS + self.tile_remaining() - 1;
+            return false;
+        }
+
+        self.fetch_step();
+        self.output_pixel();
+        self.fifo.x as usize == SCREEN_WIDTH
+    }
+
+    // The window takes over once its left edge is reached, the fetcher starts over from
+    // that dot on, which holds the pixels back for 6 dots
+    fn start_window(&mut self) {
+        if self.fifo.in_window || !self.window_visible() || (self.fifo.x as i16) < self.wx as i16 - 7 {
+            return;
+        }
+        trace!("Window starts at x {} on line {}", self.fifo.x, self.ly);
+        self.fifo.in_window = true;
+        self.fifo.background.clear();
+        self.fifo.restart_fetch();
+    }
+
+    fn sprite_at_x(&self) -> Option<usize> {
+        if self.lcdc & SPRITE_ENABLE == 0 || self.fifo.background.is_empty() || self.fifo.discard > 0 {
+            return None;
+        }
+        self.sprites.iter()
+            .enumerate()
+            .find(|(index, sprite)| self.fifo.fetched & (1 << index) == 0 && sprite.x <= self.fifo.x as i16)
+            .map(|(index, _)| index)
+    }
+
+    // The fetcher finishes the tile it is on before the sprite is fetched
+    fn tile_remaining(&self) -> u8 {
+        let offset = if self.fifo.in_window {
+            (self.fifo.x + 7).wrapping_sub(self.wx) % 8
+        } else {
+            self.fifo.x.wrapping_add(self.scx) % 8
+        };
+        5u8.saturating_sub(offset)
+    }
+
+    // Earlier sprites keep the pixels they already own, later ones only fill the gaps. With
+    // CGB priority a sprite earlier in OAM takes them over
+    fn fetch_sprite(&mut self, index: usize) {
+        let sprite = self.sprites[index];
+        let height = self.sprite_height();
+        if !self.sprite_shown(&sprite) {
+            return;
+        }
+
+        while self.fifo.sprites.len() < 8 {
+            self.fifo.sprites.push_back(SpritePixel::default());
+        }
+        for column in 0..8 {
+            let position = sprite.x + column - self.fifo.x as i16;
+            if position < 0 {
+                continue;
+            }
+            let color = self.sprite_pixel(&sprite, column, self.ly as i16 - sprite.y, height);
+            let oam_priority = !self.coordinate_priority();
+            let slot = &mut self.fifo.sprites[position as usize];
+            if color != 0 && (slot.color == 0 || (oam_priority && sprite.index < slot.index)) {
+                *slot = SpritePixel { color, attributes: sprite.attributes, index: sprite.index };
+            }
+        }
+    }
+
+    fn fetch_step(&mut self) {
+        if self.fifo.step == Step::Push {
+            if self.fifo.background.is_empty() {
+                self.push_tile();
+            }
+            return;
+        }
+
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < STEP_DOTS {
+            return;
+        }
+        self.fifo.step_dots = 0;
+
+        let (map, column, y) = if self.fifo.in_window {
+            let map = if self.lcdc & WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+            (map, self.fifo.fetch_x, self.window_line)
+        } else {
+            let map = if self.lcdc & BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+            (map, (self.scx / 8).wrapping_add(self.fifo.fetch_x) & 0x1F, self.ly.wrapping_add(self.scy))
+        };
+
+        let row = if self.fifo.attributes & FLIP_Y != 0 { 7 - y as usize % 8 } else { y as usize % 8 };
+        let data = self.tile_address(self.fifo.tile) + bank_offset(self.fifo.attributes) + row * 2;
+
+        self.fifo.step = match self.fifo.step {
+            Step::Tile => {
+                let offset = map + (y as usize / 8) * 32 + column as usize;
+                self.fifo.tile = self.vram[offset];
+                self.fifo.attributes = self.map_attributes(offset);
+                Step::DataLow
+            }
+            Step::DataLow => {
+                self.fifo.low = self.vram[data];
+                Step::DataHigh
+            }
+            Step::DataHigh => {
+                self.fifo.high = self.vram[data + 1];
+                if self.fifo.warming_up {
+                    self.fifo.warming_up = false;
+                    Step::Tile
+                } else {
+                    Step::Push
+                }
+            }
+            Step::Push => Step::Push,
+        };
+    }
+
+    fn push_tile(&mut self) {
+        let attributes = self.fifo.attributes;
+        for pixel in 0..8 {
+            let bit = if attributes & FLIP_X != 0 { pixel } else { 7 - pixel };
+            let color = ((self.fifo.high >> bit) & 0x01) << 1 | ((self.fifo.low >> bit) & 0x01);
+            self.fifo.background.push_back(BackgroundPixel { color, attributes });
+        }
+        self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+        self.fifo.step = Step::Tile;
+    }
+
+    fn output_pixel(&mut self) {
+        let background = match self.fifo.background.pop_front() {
+            Some(pixel) => pixel,
+            None => return,
+        };
+        let sprite = self.fifo.sprites.pop_front().unwrap_or_default();
+
+        if self.fifo.discard > 0 && !self.fifo.in_window {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        // Hidden layers come out as color 0, the background shows through a hidden window
+        let (color, attributes) = match (self.fifo.in_window, self.layers.background, self.layers.window) {
+            (false, true, _) | (true, _, true) => (background.color, background.attributes),
+            (true, true, false) => {
+                let map = if self.lcdc & BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+                self.map_pixel(map, self.fifo.x.wrapping_add(self.scx), self.ly.wrapping_add(self.scy))
+            }
+            _ => (0, 0),
+        };
+        let sprite = if sprite.color != 0 && self.lcdc & SPRITE_ENABLE != 0 {
+            Some((sprite.color, sprite.attributes))
+        } else {
+            None
+        };
+        let color = self.mix(color, attributes, sprite);
+        if !self.blank_frame {
+            self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.x as usize] = color;
+        }
+        self.fifo.x += 1;
+    }
+}
+
+#[cfg(test)]
+mod fifo_tests {
+    use super::*;
+
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.vram[16..32].copy_from_slice(&[0xFF; 16]);
+        ppu.vram[0x1800..0x1800 + 32].copy_from_slice(&[1; 32]);
+        ppu.write_register(BGP, 0xE4);
+        ppu.write_register(OBP0, 0xE4);
+        ppu
+    }
+
+    fn drawing_length(ppu: &mut Ppu) -> u32 {
+        drawing_length_with(ppu, LCD_ENABLE | UNSIGNED_TILES | SPRITE_ENABLE | BG_ENABLE)
+    }
+
+    fn drawing_length_with(ppu: &mut Ppu, lcdc: u8) -> u32 {
+        ppu.write_register(LCDC, lcdc);
+        ppu.tick(OAM_SCAN_CYCLES);
+        let mut dots = 0;
+        while ppu.mode == Mode::Drawing {
+            ppu.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn should_stretch_mode_3() {
+        assert_eq!(drawing_length(&mut ppu()), DRAWING_CYCLES);
+
+        let mut scrolled = ppu();
+        scrolled.write_register(SCX, 3);
+        assert_eq!(drawing_length(&mut scrolled), DRAWING_CYCLES + 3);
+
+        let mut with_sprite = ppu();
+        with_sprite.oam[0..4].copy_from_slice(&[16, 8 + 40, 1, 0]);
+        let length = drawing_length(&mut with_sprite);
+        assert!((DRAWING_CYCLES + 6..=DRAWING_CYCLES + 11).contains(&length), "mode 3 took {}", length);
+    }
+
+    #[test]
+    fn should_not_pay_for_extra_sprites() {
+        let mut limited = ppu();
+        let mut unlimited = ppu();
+        unlimited.set_layers(Layers { unlimited_sprites: true, ..Layers::default() });
+        for ppu in [&mut limited, &mut unlimited] {
+            for index in 0..12 {
+                ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, 8 + index as u8 * 8, 1, 0]);
+            }
+        }
+        assert_eq!(drawing_length(&mut unlimited), drawing_length(&mut limited));
+    }
+
+    #[test]
+    fn should_pick_up_palette_changes_mid_line() {
+        let mut ppu = ppu();
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | BG_ENABLE);
+        ppu.tick(LINES as u32 * LINE_CYCLES);
+        // Twelve dots of fetching come before the first pixel
+        ppu.tick(OAM_SCAN_CYCLES + 12 + 40);
+        ppu.write_register(BGP, 0x00);
+        ppu.tick(LINE_CYCLES);
+
+        assert_eq!(ppu.framebuffer[39], 3);
+        assert_eq!(ppu.framebuffer[40], 0);
+    }
+
+    // Line 0 of the first frame drawn, with a register write once the given pixel is next
+    // out. Pixel x leaves the FIFO on dot 13 + x, tile column c is read from the map on dot
+    // 8c + 7 and its data on dots 8c + 9 and 8c + 11
+    fn line_with_write(ppu: &mut Ppu, lcdc: u8, pixel: u32, write: impl FnOnce(&mut Ppu)) -> Vec<u16> {
+        ppu.write_register(LCDC, lcdc);
+        ppu.tick(LINES as u32 * LINE_CYCLES);
+        ppu.tick(OAM_SCAN_CYCLES + 12 + pixel);
+        write(ppu);
+        ppu.tick(LINE_CYCLES);
+        ppu.framebuffer[..SCREEN_WIDTH].to_vec()
+    }
+
+    // Where each run of one shade starts
+    fn runs(line: &[u16]) -> Vec<(usize, u16)> {
+        line.iter()
+            .enumerate()
+            .filter(|&(x, &color)| x == 0 || line[x - 1] != color)
+            .map(|(x, &color)| (x, color))
+            .collect()
+    }
+
+    // Tile 1 is solid color 3 and tile 2 solid color 1. The first 8 columns of the
+    // background map hold tile 1, the rest tile 2, the other map tile 0
+    fn striped() -> Ppu {
+        let mut ppu = ppu();
+        ppu.vram[32..48].iter_mut().step_by(2).for_each(|byte| *byte = 0xFF);
+        ppu.vram[0x1800 + 8..0x1800 + 32].copy_from_slice(&[2; 24]);
+        ppu
+    }
+
+    #[test]
+    fn should_fetch_coarse_scroll_mid_line_and_keep_fine_scroll() {
+        let unchanged = line_with_write(&mut striped(), LCD_ENABLE | UNSIGNED_TILES | BG_ENABLE, 24, |_| {});
+        assert_eq!(runs(&unchanged), vec![(0, 3), (64, 1)]);
+
+        // Column 4 is the next one read, it now comes from column 12. The fine scroll was
+        // taken as the line started
+        let scrolled = line_with_write(&mut striped(), LCD_ENABLE | UNSIGNED_TILES | BG_ENABLE, 24, |ppu| {
+            ppu.write_register(SCX, 8 * 8 + 3);
+        });
+        assert_eq!(runs(&scrolled), vec![(0, 3), (32, 1)]);
+    }
+
+    #[test]
+    fn should_pick_up_lcdc_changes_mid_line() {
+        let lcdc = LCD_ENABLE | UNSIGNED_TILES | BG_ENABLE;
+
+        // Tile 1 in the 0x8800 area is blank, the switch shows from the next data fetch
+        let signed = line_with_write(&mut ppu(), lcdc, 24, |ppu| ppu.write_register(LCDC, lcdc & !UNSIGNED_TILES));
+        assert_eq!(runs(&signed), vec![(0, 3), (32, 0)]);
+
+        // The other map holds tile 0, the switch shows from the next map read
+        let remapped = line_with_write(&mut ppu(), lcdc, 20, |ppu| ppu.write_register(LCDC, lcdc | BG_MAP));
+        assert_eq!(runs(&remapped), vec![(0, 3), (32, 0)]);
+
+        // On DMG the background goes blank from the very next pixel
+        let disabled = line_with_write(&mut ppu(), lcdc, 80, |ppu| ppu.write_register(LCDC, lcdc & !BG_ENABLE));
+        assert_eq!(runs(&disabled), vec![(0, 3), (80, 0)]);
+    }
+
+    #[test]
+    fn should_start_window_where_wx_is_when_reached() {
+        let lcdc = LCD_ENABLE | WINDOW_MAP | WINDOW_ENABLE | UNSIGNED_TILES | BG_ENABLE;
+        let window = |ppu: &mut Ppu, wx: u8| {
+            ppu.vram[32..48].iter_mut().step_by(2).for_each(|byte| *byte = 0xFF);
+            ppu.vram[0x1C00..0x1C00 + 32].copy_from_slice(&[2; 32]);
+            ppu.write_register(WX, wx);
+        };
+
+        let mut plain = ppu();
+        window(&mut plain, 7 + 80);
+        assert_eq!(drawing_length_with(&mut plain, lcdc), DRAWING_CYCLES + 6);
+
+        let mut moved = ppu();
+        window(&mut moved, 7 + 80);
+        let line = line_with_write(&mut moved, lcdc, 24, |ppu| ppu.write_register(WX, 7 + 120));
+        assert_eq!(runs(&line), vec![(0, 3), (120, 1)]);
+
+        // Once started the window stays for the rest of the line
+        let mut late = ppu();
+        window(&mut late, 7 + 80);
+        let line = line_with_write(&mut late, lcdc, 100, |ppu| ppu.write_register(WX, 7 + 140));
+        assert_eq!(runs(&line), vec![(0, 3), (80, 1)]);
+    }
+
+    #[test]
+    fn should_match_scanline_output() {
+        let hidden = Layers { window: false, hidden_sprites: 1 << 1, unlimited_sprites: true, ..Layers::default() };
+        for (cgb, layers) in [(false, Layers::default()), (true, Layers::default()), (false, hidden), (true, hidden)] {
+            let mut fifo = ppu();
+            let mut scanline = ppu();
+            scanline.set_renderer(Renderer::Scanline);
+
+            for ppu in [&mut fifo, &mut scanline] {
+                ppu.set_cgb(cgb);
+                ppu.set_layers(layers);
+                ppu.vram[0x1800 + 5] = 0;
+                ppu.vram[VRAM_SIZE + 0x1800 + 2] = FLIP_X | FLIP_Y | 3;
+                ppu.vram[VRAM_SIZE + 0x1800 + 3] = BEHIND_BG;
+                ppu.vram[18] = 0x0F;
+                ppu.oam[0..4].copy_from_slice(&[20, 30, 1, FLIP_X]);
+                ppu.oam[4..8].copy_from_slice(&[20, 34, 0, 0]);
+                ppu.oam[8..12].copy_from_slice(&[16, 24, 1, 2]);
+                ppu.write_register(SCX, 13);
+                ppu.write_register(WX, 7 + 100);
+                ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | WINDOW_MAP | WINDOW_ENABLE | SPRITE_ENABLE | BG_ENABLE);
+                ppu.write_register(BCPS, AUTO_INCREMENT);
+                ppu.write_register(OCPS, AUTO_INCREMENT);
+                for data in 0..PALETTE_RAM_SIZE as u8 {
+                    ppu.write_register(BCPD, data.wrapping_mul(37));
+                    ppu.write_register(OCPD, data.wrapping_mul(91));
+                }
+                // The first frame after enabling the LCD stays blank
+                ppu.tick(2 * LINES as u32 * LINE_CYCLES);
+            }
+            assert!(fifo.framebuffer == scanline.framebuffer, "frames differ in {} mode with {:?}", if cgb { "CGB" } else { "DMG" }, layers);
+        }
+    }
+}
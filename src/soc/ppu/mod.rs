@@ -0,0 +1,1014 @@
+use std::mem;
+use std::str::FromStr;
+use log::{debug, trace};
+use crate::memory::Address;
+use fifo::PixelFifo;
+pub use viewer::Image;
+
+mod fifo;
+mod oam_bug;
+mod viewer;
+
+// https://gbdev.io/pandocs/Rendering.html
+// The scanline renderer draws lines whole when mode 3 ends, the pixel FIFO one (fifo.rs)
+// follows mid-line register changes.
+// Color games get a second VRAM bank for map attributes and tiles, and palette RAM
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const VRAM_SIZE: usize = 0x2000;
+pub const OAM_SIZE: usize = 0xA0;
+pub const OAM_ENTRIES: usize = OAM_SIZE / 4;
+
+pub const LCDC: Address = 0xFF40;
+pub const STAT: Address = 0xFF41;
+pub const SCY: Address = 0xFF42;
+pub const SCX: Address = 0xFF43;
+pub const LY: Address = 0xFF44;
+pub const LYC: Address = 0xFF45;
+pub const BGP: Address = 0xFF47;
+pub const OBP0: Address = 0xFF48;
+pub const OBP1: Address = 0xFF49;
+pub const WY: Address = 0xFF4A;
+pub const WX: Address = 0xFF4B;
+pub const VBK: Address = 0xFF4F;
+pub const BCPS: Address = 0xFF68;
+pub const BCPD: Address = 0xFF69;
+pub const OCPS: Address = 0xFF6A;
+pub const OCPD: Address = 0xFF6B;
+pub const OPRI: Address = 0xFF6C;
+
+const OAM_SCAN_CYCLES: u32 = 80;
+const DRAWING_CYCLES: u32 = 172;
+const LINE_CYCLES: u32 = 456;
+const VISIBLE_LINES: u8 = 144;
+const LINES: u8 = 154;
+const FRAME_CYCLES: u32 = LINES as u32 * LINE_CYCLES;
+// LY already reads 0 for most of line 153
+const LINE_153_CYCLES: u32 = 4;
+
+// LCDC bits
+const LCD_ENABLE: u8 = 0x80;
+const WINDOW_MAP: u8 = 0x40;
+const WINDOW_ENABLE: u8 = 0x20;
+const UNSIGNED_TILES: u8 = 0x10;
+const BG_MAP: u8 = 0x08;
+const TALL_SPRITES: u8 = 0x04;
+const SPRITE_ENABLE: u8 = 0x02;
+const BG_ENABLE: u8 = 0x01;
+
+// STAT bits
+const STAT_WRITABLE: u8 = 0x78;
+const LYC_SOURCE: u8 = 0x40;
+const OAM_SOURCE: u8 = 0x20;
+const VBLANK_SOURCE: u8 = 0x10;
+const HBLANK_SOURCE: u8 = 0x08;
+const COINCIDENCE: u8 = 0x04;
+
+// IF bits
+const VBLANK_INTERRUPT: u8 = 0x01;
+const STAT_INTERRUPT: u8 = 0x02;
+
+// OAM attribute bits, CGB map attributes use the same layout with BEHIND_BG giving the
+// tile priority over sprites
+const BEHIND_BG: u8 = 0x80;
+const FLIP_Y: u8 = 0x40;
+const FLIP_X: u8 = 0x20;
+const SECOND_PALETTE: u8 = 0x10;
+const VRAM_BANK: u8 = 0x08;
+const CGB_PALETTE: u8 = 0x07;
+
+// BCPS and OCPS bits
+const AUTO_INCREMENT: u8 = 0x80;
+const PALETTE_INDEX: u8 = 0x3F;
+const PALETTE_RAM_SIZE: usize = 64;
+
+// OPRI set gives sprites the DMG priority by X coordinate instead of by OAM index
+const COORDINATE_PRIORITY: u8 = 0x01;
+
+// BGR555
+const WHITE: u16 = 0x7FFF;
+
+// DMG shades from white to black, as plain grays
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// Scanline draws whole lines at once, the pixel FIFO follows the hardware dot by dot for
+// games changing registers during mode 3
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+// How the CPU touched the OAM area, which decides the way the OAM bug mangles it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OamCorruption {
+    Write,
+    Read,
+    ReadIncrease,
+}
+
+// Debugging switches over what ends up in the framebuffer. LCDC and the timing of the line
+// are left as they are, the game cannot tell
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub sprites: bool,
+    // One bit per OAM entry
+    pub hidden_sprites: u64,
+    // Sprites past the tenth of a line are drawn too, mode 3 still only pays for ten
+    pub unlimited_sprites: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers {
+            background: true,
+            window: true,
+            sprites: true,
+            hidden_sprites: 0,
+            unlimited_sprites: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: i16,
+    x: i16,
+    tile: u8,
+    attributes: u8,
+    // Position in OAM, what CGB priority goes by
+    index: u8,
+    // Over the hardware limit, only there with unlimited sprites
+    extra: bool,
+}
+
+pub struct Ppu {
+    // Both CGB banks, bank 1 holds the map attributes and more tiles
+    vram: [u8; 2 * VRAM_SIZE],
+    oam: [u8; OAM_SIZE],
+    cgb: bool,
+
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    vram_bank: u8,
+    bcps: u8,
+    ocps: u8,
+    opri: u8,
+    background_palettes: [u8; PALETTE_RAM_SIZE],
+    sprite_palettes: [u8; PALETTE_RAM_SIZE],
+
+    mode: Mode,
+    line_cycles: u32,
+    renderer: Renderer,
+    layers: Layers,
+    fifo: PixelFifo,
+    // Length of mode 3 on the current line, the rest of the line is HBlank
+    drawing_cycles: u32,
+    // Every STAT source shares one interrupt line, only its rising edge requests an
+    // interrupt so a source going high while another one holds it is lost
+    stat_line: bool,
+    // Requested interrupts not yet handed to IF
+    interrupts: u8,
+    // Line 0 after switching the LCD on skips mode 2 and the frame is not shown
+    first_line: bool,
+    blank_frame: bool,
+    // A frame is out, set when VBlank starts
+    frame_ready: bool,
+    // A switched off LCD still shows frames, blank ones
+    off_cycles: u32,
+    // The window keeps its own line counter, it does not move on lines it is hidden
+    window_line: u8,
+    sprites: Vec<Sprite>,
+
+    // Shades 0 (white) to 3 (black) on DMG, BGR555 colors on CGB, palettes applied
+    framebuffer: Vec<u16>,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            vram: [0; 2 * VRAM_SIZE],
+            oam: [0; OAM_SIZE],
+            cgb: false,
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            vram_bank: 0,
+            bcps: 0,
+            ocps: 0,
+            opri: 0,
+            background_palettes: [0xFF; PALETTE_RAM_SIZE],
+            sprite_palettes: [0xFF; PALETTE_RAM_SIZE],
+            mode: Mode::HBlank,
+            line_cycles: 0,
+            renderer: Renderer::Scanline,
+            layers: Layers::default(),
+            fifo: PixelFifo::new(),
+            drawing_cycles: DRAWING_CYCLES,
+            stat_line: false,
+            interrupts: 0,
+            first_line: false,
+            blank_frame: false,
+            frame_ready: false,
+            off_cycles: 0,
+            window_line: 0,
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        debug!("Using {:?} renderer", renderer);
+        self.renderer = renderer;
+    }
+
+    pub fn set_layers(&mut self, layers: Layers) {
+        debug!("Drawing layers {:?}", layers);
+        self.layers = layers;
+    }
+
+    // Color games boot with both VRAM banks, CGB registers and palettes. Only a reset or a
+    // cartridge swap changes the mode
+    pub fn set_cgb(&mut self, cgb: bool) {
+        debug!("PPU running in {} mode", if cgb { "CGB" } else { "DMG" });
+        self.cgb = cgb;
+        let blank = self.blank();
+        self.framebuffer.iter_mut().for_each(|color| *color = blank);
+    }
+
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    // The bank selected by VBK, as the CPU sees it
+    pub fn vram(&self) -> &[u8] {
+        let start = self.vram_bank as usize * VRAM_SIZE;
+        &self.vram[start..start + VRAM_SIZE]
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        let start = self.vram_bank as usize * VRAM_SIZE;
+        &mut self.vram[start..start + VRAM_SIZE]
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.oam
+    }
+
+    // The CPU is locked out of VRAM while the PPU draws from it, and out of OAM from the
+    // scan on. With the LCD off both are always free
+    pub fn vram_accessible(&self) -> bool {
+        self.visible_mode() != Mode::Drawing
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !matches!(self.visible_mode(), Mode::OamScan | Mode::Drawing)
+    }
+
+    pub fn read_register(&self, address: Address) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.coincidence() { COINCIDENCE } else { 0 };
+                0x80 | (self.stat & STAT_WRITABLE) | coincidence | self.visible_mode() as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.visible_ly(),
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            VBK if self.cgb => 0xFE | self.vram_bank,
+            BCPS if self.cgb => 0x40 | self.bcps,
+            BCPD if self.cgb => self.background_palettes[(self.bcps & PALETTE_INDEX) as usize],
+            OCPS if self.cgb => 0x40 | self.ocps,
+            OCPD if self.cgb => self.sprite_palettes[(self.ocps & PALETTE_INDEX) as usize],
+            OPRI if self.cgb => 0xFE | self.opri,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: Address, data: u8) {
+        trace!("PPU register {:#X} set to {:#X}", address, data);
+
+        match address {
+            LCDC => {
+                let was_enabled = self.enabled();
+                self.lcdc = data;
+                match (was_enabled, self.enabled()) {
+                    (true, false) => self.switch_off(),
+                    (false, true) => self.switch_on(),
+                    _ => {}
+                }
+            }
+            STAT => {
+                self.stat = data & STAT_WRITABLE;
+                self.update_stat_line();
+            }
+            SCY => self.scy = data,
+            SCX => self.scx = data,
+            LYC => {
+                self.lyc = data;
+                self.update_stat_line();
+            }
+            BGP => self.bgp = data,
+            OBP0 => self.obp0 = data,
+            OBP1 => self.obp1 = data,
+            WY => self.wy = data,
+            WX => self.wx = data,
+            VBK if self.cgb => self.vram_bank = data & 0x01,
+            BCPS if self.cgb => self.bcps = data & (AUTO_INCREMENT | PALETTE_INDEX),
+            BCPD if self.cgb => write_palette(&mut self.background_palettes, &mut self.bcps, data),
+            OCPS if self.cgb => self.ocps = data & (AUTO_INCREMENT | PALETTE_INDEX),
+            OCPD if self.cgb => write_palette(&mut self.sprite_palettes, &mut self.ocps, data),
+            OPRI if self.cgb => self.opri = data & COORDINATE_PRIORITY,
+            // LY is read only, CGB registers do not exist on DMG
+            _ => {}
+        }
+    }
+
+    // Interrupts requested since the last call, as IF bits
+    pub fn take_interrupts(&mut self) -> u8 {
+        mem::take(&mut self.interrupts)
+    }
+
+    // Whether a whole frame was drawn since the last call
+    pub fn take_frame(&mut self) -> bool {
+        mem::take(&mut self.frame_ready)
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled() {
+            self.off_cycles += cycles;
+            if self.off_cycles >= FRAME_CYCLES {
+                self.off_cycles -= FRAME_CYCLES;
+                self.frame_ready = true;
+            }
+            return;
+        }
+
+        self.line_cycles += cycles;
+        loop {
+            if self.mode == Mode::Drawing && self.renderer == Renderer::Fifo {
+                if !self.draw_dots() {
+                    break;
+                }
+                self.mode = Mode::HBlank;
+                self.update_stat_line();
+                continue;
+            }
+
+            let duration = match self.mode {
+                Mode::OamScan => OAM_SCAN_CYCLES,
+                Mode::Drawing => DRAWING_CYCLES,
+                Mode::HBlank => LINE_CYCLES - OAM_SCAN_CYCLES - self.drawing_cycles,
+                Mode::VBlank => LINE_CYCLES,
+            };
+            if self.line_cycles < duration {
+                break;
+            }
+            self.line_cycles -= duration;
+
+            match self.mode {
+                Mode::OamScan => {
+                    self.scan_oam();
+                    if self.renderer == Renderer::Fifo {
+                        self.start_fifo_line();
+                    } else {
+                        self.drawing_cycles = DRAWING_CYCLES;
+                    }
+                    self.first_line = false;
+                    self.mode = Mode::Drawing;
+                }
+                Mode::Drawing => {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                }
+                Mode::HBlank | Mode::VBlank => self.next_line(),
+            }
+            self.update_stat_line();
+        }
+        // LY moves to 0 partway through line 153
+        self.update_stat_line();
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    // The screen goes blank and LY stays at 0 until the LCD is enabled again
+    fn switch_off(&mut self) {
+        debug!("LCD switched off on line {}", self.ly);
+        self.ly = 0;
+        self.line_cycles = 0;
+        self.mode = Mode::HBlank;
+        self.stat_line = false;
+        let blank = self.blank();
+        self.framebuffer.iter_mut().for_each(|color| *color = blank);
+    }
+
+    fn blank(&self) -> u16 {
+        if self.cgb { WHITE } else { 0 }
+    }
+
+    // LY=LYC is checked right away, the mode 2 source does not fire for line 0
+    fn switch_on(&mut self) {
+        debug!("LCD switched on");
+        self.ly = 0;
+        self.line_cycles = 0;
+        self.window_line = 0;
+        self.mode = Mode::OamScan;
+        self.first_line = true;
+        self.blank_frame = true;
+        self.update_stat_line();
+    }
+
+    fn visible_ly(&self) -> u8 {
+        if self.ly == LINES - 1 && self.line_cycles >= LINE_153_CYCLES { 0 } else { self.ly }
+    }
+
+    fn visible_mode(&self) -> Mode {
+        if self.first_line { Mode::HBlank } else { self.mode }
+    }
+
+    fn coincidence(&self) -> bool {
+        self.visible_ly() == self.lyc
+    }
+
+    fn update_stat_line(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+
+        let source = match self.visible_mode() {
+            Mode::HBlank => HBLANK_SOURCE,
+            Mode::VBlank => VBLANK_SOURCE,
+            Mode::OamScan => OAM_SOURCE,
+            Mode::Drawing => 0,
+        };
+        let line = self.stat & source != 0 || (self.stat & LYC_SOURCE != 0 && self.coincidence());
+        if line && !self.stat_line {
+            trace!("STAT interrupt on line {} in mode {:?}", self.visible_ly(), self.visible_mode());
+            self.interrupts |= STAT_INTERRUPT;
+        }
+        self.stat_line = line;
+    }
+
+    fn next_line(&mut self) {
+        if self.mode == Mode::HBlank && self.window_visible() {
+            self.window_line += 1;
+        }
+
+        self.ly = (self.ly + 1) % LINES;
+        self.mode = match self.ly {
+            0 => {
+                self.window_line = 0;
+                self.blank_frame = false;
+                Mode::OamScan
+            }
+            VISIBLE_LINES => {
+                self.interrupts |= VBLANK_INTERRUPT;
+                self.frame_ready = true;
+                Mode::VBlank
+            }
+            ly if ly > VISIBLE_LINES => Mode::VBlank,
+            _ => Mode::OamScan,
+        };
+    }
+
+    // The first ten sprites overlapping the line in OAM order. On DMG they are then sorted
+    // by X so that the leftmost one wins, OAM order breaking ties, CGB sticks to OAM order
+    fn scan_oam(&mut self) {
+        let height = self.sprite_height();
+        let ly = self.ly as i16;
+        let limit = if self.layers.unlimited_sprites { OAM_ENTRIES } else { SPRITES_PER_LINE };
+
+        self.sprites.clear();
+        for (index, entry) in self.oam.chunks(4).enumerate() {
+            let y = entry[0] as i16 - 16;
+            if ly >= y && ly < y + height {
+                let extra = self.sprites.len() >= SPRITES_PER_LINE;
+                let sprite = Sprite { y, x: entry[1] as i16 - 8, tile: entry[2], attributes: entry[3], index: index as u8, extra };
+                self.sprites.push(sprite);
+                if self.sprites.len() == limit {
+                    break;
+                }
+            }
+        }
+        if self.coordinate_priority() {
+            self.sprites.sort_by_key(|sprite| sprite.x);
+        }
+    }
+
+    fn coordinate_priority(&self) -> bool {
+        !self.cgb || self.opri & COORDINATE_PRIORITY != 0
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.lcdc & TALL_SPRITES != 0 { 16 } else { 8 }
+    }
+
+    // On DMG clearing LCDC bit 0 hides the window as well, on CGB it only takes the priority
+    // away from background and window
+    fn window_visible(&self) -> bool {
+        let background = self.cgb || self.lcdc & BG_ENABLE != 0;
+        self.lcdc & WINDOW_ENABLE != 0 && background && self.ly >= self.wy && self.wx <= 166
+    }
+
+    fn render_line(&mut self) {
+        // The frame following LCD enable stays white
+        if self.blank_frame {
+            return;
+        }
+        let mut colors = [0u8; SCREEN_WIDTH];
+        let mut attributes = [0u8; SCREEN_WIDTH];
+
+        // Hidden layers stay color 0, the background shows through a hidden window
+        if self.cgb || self.lcdc & BG_ENABLE != 0 {
+            if self.layers.background {
+                self.render_background(&mut colors, &mut attributes);
+            }
+            if self.window_visible() && self.layers.window {
+                self.render_window(&mut colors, &mut attributes);
+            }
+        }
+
+        let line = self.ly as usize * SCREEN_WIDTH;
+        for x in 0..SCREEN_WIDTH {
+            let sprite = if self.lcdc & SPRITE_ENABLE != 0 { self.line_sprite_pixel(x as i16) } else { None };
+            self.framebuffer[line + x] = self.mix(colors[x], attributes[x], sprite);
+        }
+    }
+
+    fn render_background(&self, colors: &mut [u8; SCREEN_WIDTH], attributes: &mut [u8; SCREEN_WIDTH]) {
+        let map = if self.lcdc & BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+        let y = self.ly.wrapping_add(self.scy);
+
+        for x in 0..SCREEN_WIDTH {
+            (colors[x], attributes[x]) = self.map_pixel(map, (x as u8).wrapping_add(self.scx), y);
+        }
+    }
+
+    fn render_window(&self, colors: &mut [u8; SCREEN_WIDTH], attributes: &mut [u8; SCREEN_WIDTH]) {
+        let map = if self.lcdc & WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+        let start = self.wx as i16 - 7;
+
+        for x in start.max(0) as usize..SCREEN_WIDTH {
+            (colors[x], attributes[x]) = self.map_pixel(map, (x as i16 - start) as u8, self.window_line);
+        }
+    }
+
+    // Color index and attributes of a pixel of the 256x256 tile map starting at the given
+    // VRAM offset
+    fn map_pixel(&self, map: usize, x: u8, y: u8) -> (u8, u8) {
+        let offset = map + (y as usize / 8) * 32 + x as usize / 8;
+        let attributes = self.map_attributes(offset);
+        let tile = self.tile_address(self.vram[offset]) + bank_offset(attributes);
+        let x = if attributes & FLIP_X != 0 { 7 - x % 8 } else { x % 8 };
+        let y = if attributes & FLIP_Y != 0 { 7 - y % 8 } else { y % 8 };
+        (self.tile_pixel(tile, x, y), attributes)
+    }
+
+    // Bank 1 mirrors the tile map with the attributes of every tile, DMG has none
+    fn map_attributes(&self, offset: usize) -> u8 {
+        if self.cgb { self.vram[VRAM_SIZE + offset] } else { 0 }
+    }
+
+    // 0x8000 addressing counts tiles from 0x8000, 0x8800 addressing is signed from 0x9000
+    fn tile_address(&self, index: u8) -> usize {
+        if self.lcdc & UNSIGNED_TILES != 0 {
+            index as usize * 16
+        } else {
+            (0x1000 + (index as i8 as isize) * 16) as usize
+        }
+    }
+
+    fn tile_pixel(&self, tile: usize, x: u8, y: u8) -> u8 {
+        let low = self.vram[tile + y as usize * 2];
+        let high = self.vram[tile + y as usize * 2 + 1];
+        let bit = 7 - x;
+        ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01)
+    }
+
+    // Color and attributes of the first opaque sprite pixel at the given X
+    fn line_sprite_pixel(&self, x: i16) -> Option<(u8, u8)> {
+        let height = self.sprite_height();
+        self.sprites.iter()
+            .filter(|sprite| x >= sprite.x && x < sprite.x + 8 && self.sprite_shown(sprite))
+            .find_map(|sprite| {
+                let color = self.sprite_pixel(sprite, x - sprite.x, self.ly as i16 - sprite.y, height);
+                if color == 0 { None } else { Some((color, sprite.attributes)) }
+            })
+    }
+
+    // Final color of a pixel out of the background or window one and the opaque sprite pixel
+    // on top of it, if any
+    fn mix(&self, color: u8, attributes: u8, sprite: Option<(u8, u8)>) -> u16 {
+        // On DMG clearing bit 0 blanks both background and window, on CGB sprites then
+        // always win
+        let priority = self.lcdc & BG_ENABLE != 0;
+        let color = if priority || self.cgb { color } else { 0 };
+
+        if let Some((sprite_color, sprite_attributes)) = sprite {
+            let hidden = priority && color != 0 && (sprite_attributes | attributes) & BEHIND_BG != 0;
+            if !hidden && self.cgb {
+                return palette_color(&self.sprite_palettes, sprite_attributes & CGB_PALETTE, sprite_color);
+            } else if !hidden {
+                let palette = if sprite_attributes & SECOND_PALETTE != 0 { self.obp1 } else { self.obp0 };
+                return shade(palette, sprite_color) as u16;
+            }
+        }
+
+        if self.cgb {
+            palette_color(&self.background_palettes, attributes & CGB_PALETTE, color)
+        } else {
+            shade(self.bgp, color) as u16
+        }
+    }
+
+    fn sprite_shown(&self, sprite: &Sprite) -> bool {
+        self.layers.sprites && self.layers.hidden_sprites & (1 << sprite.index) == 0
+    }
+
+    fn sprite_pixel(&self, sprite: &Sprite, x: i16, y: i16, height: i16) -> u8 {
+        let x = if sprite.attributes & FLIP_X != 0 { 7 - x } else { x };
+        let y = if sprite.attributes & FLIP_Y != 0 { height - 1 - y } else { y };
+
+        // Tall sprites ignore bit 0 of the tile index, the bottom half is the next tile
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile } as usize;
+        let bank = if self.cgb { bank_offset(sprite.attributes) } else { 0 };
+        self.tile_pixel(bank + tile * 16 + (y as usize / 8) * 16, x as u8, y as u8 % 8)
+    }
+}
+
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+fn bank_offset(attributes: u8) -> usize {
+    if attributes & VRAM_BANK != 0 { VRAM_SIZE } else { 0 }
+}
+
+// Eight palettes of four little endian BGR555 colors
+fn palette_color(palettes: &[u8; PALETTE_RAM_SIZE], palette: u8, color: u8) -> u16 {
+    let offset = palette as usize * 8 + color as usize * 2;
+    u16::from_le_bytes([palettes[offset], palettes[offset + 1]]) & WHITE
+}
+
+// CGB colors are BGR555, each 5 bit channel is stretched over the full 8 bits
+pub fn to_rgb(color: u16, cgb: bool) -> [u8; 3] {
+    if !cgb {
+        let shade = SHADES[color as usize & 0x03];
+        return [shade; 3];
+    }
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1F) as u8;
+        value << 3 | value >> 2
+    };
+    [channel(0), channel(5), channel(10)]
+}
+
+// Data goes where BCPS or OCPS points, which moves on to the next byte if asked to
+fn write_palette(palettes: &mut [u8; PALETTE_RAM_SIZE], specification: &mut u8, data: u8) {
+    palettes[(*specification & PALETTE_INDEX) as usize] = data;
+    if *specification & AUTO_INCREMENT != 0 {
+        *specification = AUTO_INCREMENT | ((*specification + 1) & PALETTE_INDEX);
+    }
+}
+
+impl FromStr for Renderer {
+    type Err = String;
+
+    fn from_str(renderer: &str) -> Result<Self, Self::Err> {
+        match renderer.to_ascii_lowercase().as_str() {
+            "scanline" => Ok(Renderer::Scanline),
+            "fifo" => Ok(Renderer::Fifo),
+            _ => Err(format!("Unknown renderer {}, expected scanline or fifo", renderer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod ppu_tests {
+    use super::*;
+
+    // Tile 1 is solid color 3, tile 2 solid color 1
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.vram[16..32].copy_from_slice(&[0xFF; 16]);
+        ppu.vram[32..48].iter_mut().step_by(2).for_each(|byte| *byte = 0xFF);
+        ppu.write_register(BGP, 0xE4);
+        ppu.write_register(OBP0, 0xE4);
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | SPRITE_ENABLE | BG_ENABLE);
+        // Past the blank frame following LCD enable
+        ppu.tick(LINES as u32 * LINE_CYCLES);
+        ppu
+    }
+
+    fn run_frame(ppu: &mut Ppu) {
+        for _ in 0..LINES as u32 * LINE_CYCLES / 4 {
+            ppu.tick(4);
+        }
+    }
+
+    #[test]
+    fn should_follow_mode_timing() {
+        let mut ppu = ppu();
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::OamScan as u8);
+
+        ppu.tick(OAM_SCAN_CYCLES);
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::Drawing as u8);
+        ppu.tick(DRAWING_CYCLES);
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::HBlank as u8);
+        ppu.tick(LINE_CYCLES - OAM_SCAN_CYCLES - DRAWING_CYCLES);
+        assert_eq!(ppu.read_register(LY), 1);
+
+        ppu.tick(143 * LINE_CYCLES);
+        assert_eq!(ppu.read_register(LY), 144);
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::VBlank as u8);
+
+        ppu.tick(10 * LINE_CYCLES);
+        assert_eq!(ppu.read_register(LY), 0);
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::OamScan as u8);
+    }
+
+    #[test]
+    fn should_scroll_background_with_signed_tiles() {
+        let mut ppu = ppu();
+        // Tile -127 at 0x8810 holds color 3 in 0x8800 addressing
+        ppu.vram[0x0810..0x0820].copy_from_slice(&[0xFF; 16]);
+        ppu.vram[0x1800 + 32 + 1] = 0x81;
+        ppu.write_register(LCDC, LCD_ENABLE | BG_ENABLE);
+        ppu.write_register(SCX, 4);
+        ppu.write_register(SCY, 8);
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[3], 0);
+        assert_eq!(ppu.framebuffer[4], 3);
+        assert_eq!(ppu.framebuffer[11], 3);
+        assert_eq!(ppu.framebuffer[12], 0);
+    }
+
+    #[test]
+    fn should_draw_window_over_background() {
+        let mut ppu = ppu();
+        ppu.vram[0x1C00..0x1C00 + 32].copy_from_slice(&[1; 32]);
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | WINDOW_MAP | WINDOW_ENABLE | BG_ENABLE);
+        ppu.write_register(WX, 7 + 80);
+        ppu.write_register(WY, 10);
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[9 * SCREEN_WIDTH + 80], 0);
+        assert_eq!(ppu.framebuffer[10 * SCREEN_WIDTH + 79], 0);
+        assert_eq!(ppu.framebuffer[10 * SCREEN_WIDTH + 80], 3);
+    }
+
+    #[test]
+    fn should_limit_sprites_per_line() {
+        let mut ppu = ppu();
+        for index in 0..12 {
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, 8 + index as u8 * 8, 1, 0]);
+        }
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[9 * 8], 3);
+        assert_eq!(ppu.framebuffer[10 * 8], 0);
+    }
+
+    #[test]
+    fn should_flip_tall_sprites_and_hide_behind_background() {
+        let mut ppu = ppu();
+        // Tall sprite made of tiles 2 and 3, tile 3 only has its top row set
+        ppu.vram[48] = 0xFF;
+        ppu.oam[0..4].copy_from_slice(&[16, 8, 3, FLIP_Y]);
+        // Tiles 0 and 1, behind the color 1 background tile at row 1 column 1
+        ppu.oam[4..8].copy_from_slice(&[16, 16, 1, BEHIND_BG]);
+        ppu.vram[0x1800 + 32 + 1] = 2;
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | TALL_SPRITES | SPRITE_ENABLE | BG_ENABLE);
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[0], 0);
+        assert_eq!(ppu.framebuffer[7 * SCREEN_WIDTH], 1);
+        assert_eq!(ppu.framebuffer[8 * SCREEN_WIDTH + 8], 1);
+    }
+
+    #[test]
+    fn should_hide_layers_without_touching_lcdc() {
+        let mut ppu = ppu();
+        ppu.vram[0x1800..0x1800 + 32].copy_from_slice(&[2; 32]);
+        ppu.vram[0x1C00..0x1C00 + 32].copy_from_slice(&[1; 32]);
+        ppu.oam[0..4].copy_from_slice(&[16, 8 + 40, 1, 0]);
+        ppu.oam[4..8].copy_from_slice(&[16, 8 + 48, 1, 0]);
+        let lcdc = LCD_ENABLE | UNSIGNED_TILES | WINDOW_MAP | WINDOW_ENABLE | SPRITE_ENABLE | BG_ENABLE;
+        ppu.write_register(LCDC, lcdc);
+        ppu.write_register(WX, 7 + 80);
+
+        ppu.set_layers(Layers { background: false, window: false, hidden_sprites: 1 << 1, ..Layers::default() });
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_register(LCDC), lcdc);
+        assert_eq!(ppu.framebuffer[0], 0);
+        assert_eq!(ppu.framebuffer[80], 0);
+        assert_eq!(ppu.framebuffer[40], 3);
+        assert_eq!(ppu.framebuffer[48], 0);
+
+        // The background shows through the hidden window
+        ppu.set_layers(Layers { window: false, sprites: false, ..Layers::default() });
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[80], 1);
+        assert_eq!(ppu.framebuffer[40], 1);
+    }
+
+    #[test]
+    fn should_lift_sprite_limit_on_request() {
+        let mut ppu = ppu();
+        for index in 0..12 {
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, 8 + index as u8 * 8, 1, 0]);
+        }
+        ppu.set_layers(Layers { unlimited_sprites: true, ..Layers::default() });
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[10 * 8], 3);
+        assert_eq!(ppu.framebuffer[11 * 8 + 7], 3);
+    }
+
+    #[test]
+    fn should_share_one_stat_line_between_sources() {
+        let mut ppu = ppu();
+        ppu.write_register(LYC, 1);
+        ppu.write_register(STAT, HBLANK_SOURCE | LYC_SOURCE);
+        ppu.take_interrupts();
+
+        ppu.tick(OAM_SCAN_CYCLES + DRAWING_CYCLES);
+        assert_eq!(ppu.take_interrupts(), STAT_INTERRUPT);
+
+        // LY=LYC on line 1 keeps the line high from the previous HBlank, nothing fires
+        ppu.tick(LINE_CYCLES);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        ppu.tick(LINE_CYCLES);
+        assert_eq!(ppu.take_interrupts(), STAT_INTERRUPT);
+    }
+
+    #[test]
+    fn should_read_ly_as_0_through_line_153() {
+        let mut ppu = ppu();
+        ppu.write_register(STAT, LYC_SOURCE);
+        ppu.tick(153 * LINE_CYCLES);
+        assert_eq!(ppu.read_register(LY), 153);
+        assert_eq!(ppu.take_interrupts(), VBLANK_INTERRUPT | STAT_INTERRUPT);
+
+        ppu.tick(LINE_153_CYCLES);
+        assert_eq!(ppu.read_register(LY), 0);
+        assert_eq!(ppu.read_register(STAT) & COINCIDENCE, COINCIDENCE);
+        assert_eq!(ppu.take_interrupts(), STAT_INTERRUPT);
+
+        // Still the same LY=LYC match once line 0 really starts
+        ppu.tick(LINE_CYCLES - LINE_153_CYCLES);
+        assert_eq!(ppu.read_register(LY), 0);
+        assert_eq!(ppu.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn should_blank_first_frame_after_enabling_lcd() {
+        let mut ppu = ppu();
+        ppu.vram[0x1800] = 1;
+        ppu.write_register(STAT, OAM_SOURCE);
+        ppu.write_register(LCDC, 0);
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | BG_ENABLE);
+        ppu.take_interrupts();
+
+        // No mode 2 on line 0, the first one comes with line 1
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::HBlank as u8);
+        ppu.tick(OAM_SCAN_CYCLES);
+        assert_eq!(ppu.take_interrupts(), 0);
+        assert_eq!(ppu.read_register(STAT) & 0x03, Mode::Drawing as u8);
+        ppu.tick(LINE_CYCLES - OAM_SCAN_CYCLES);
+        assert_eq!(ppu.take_interrupts(), STAT_INTERRUPT);
+        assert_eq!(ppu.framebuffer[0], 0);
+
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[0], 3);
+    }
+
+    // Background palette 2 and sprite palette 1 with distinct colors
+    fn cgb_ppu() -> Ppu {
+        let mut ppu = ppu();
+        ppu.set_cgb(true);
+        ppu.write_register(BCPS, AUTO_INCREMENT | 16);
+        for data in [0x00, 0x01, 0x1F, 0x00, 0x00, 0x02, 0x00, 0x03] {
+            ppu.write_register(BCPD, data);
+        }
+        ppu.write_register(OCPS, AUTO_INCREMENT | 8);
+        for data in [0x00, 0x00, 0x00, 0x7C, 0xE0, 0x03, 0x00, 0x00] {
+            ppu.write_register(OCPD, data);
+        }
+        ppu
+    }
+
+    #[test]
+    fn should_auto_increment_palette_ram() {
+        let mut ppu = cgb_ppu();
+        ppu.write_register(BCPS, AUTO_INCREMENT | 0x3F);
+        ppu.write_register(BCPD, 0x12);
+        ppu.write_register(BCPD, 0x34);
+        assert_eq!(ppu.read_register(BCPS), 0xC1);
+        ppu.write_register(BCPS, 0x00);
+        assert_eq!(ppu.read_register(BCPD), 0x34);
+        assert_eq!(ppu.read_register(BCPD), 0x34);
+
+        let mut dmg = Ppu::new();
+        dmg.write_register(BCPS, 0x00);
+        assert_eq!(dmg.read_register(BCPS), 0xFF);
+        assert_eq!(dmg.read_register(VBK), 0xFF);
+    }
+
+    #[test]
+    fn should_decode_cgb_map_attributes() {
+        let mut ppu = cgb_ppu();
+        // Tile 1 of bank 1 only has its leftmost column set, in color 1
+        ppu.write_register(VBK, 1);
+        ppu.vram_mut()[16..32].iter_mut().step_by(2).for_each(|byte| *byte = 0x80);
+        ppu.vram_mut()[0x1800] = VRAM_BANK | FLIP_X | 2;
+        ppu.write_register(VBK, 0);
+        ppu.vram[0x1800] = 1;
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.framebuffer[0], 0x0100);
+        assert_eq!(ppu.framebuffer[7], 0x001F);
+        assert_eq!(ppu.framebuffer[8], 0x7FFF);
+    }
+
+    #[test]
+    fn should_follow_cgb_sprite_priority() {
+        let mut ppu = cgb_ppu();
+        // Solid color 3 sprites overlapping on pixels 12 to 15, OAM order wins over X
+        ppu.oam[0..4].copy_from_slice(&[16, 8 + 12, 1, 1]);
+        ppu.oam[4..8].copy_from_slice(&[16, 8 + 8, 1, 0]);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[12], 0x0000);
+        assert_eq!(ppu.framebuffer[11], 0x7FFF);
+
+        ppu.write_register(OPRI, COORDINATE_PRIORITY);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[12], 0x7FFF);
+
+        // Background priority over a sprite only holds while LCDC bit 0 is set
+        ppu.vram[VRAM_SIZE + 0x1800 + 1] = BEHIND_BG | 2;
+        ppu.vram[0x1800 + 1] = 1;
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[8], 0x0300);
+        ppu.write_register(LCDC, LCD_ENABLE | UNSIGNED_TILES | SPRITE_ENABLE);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.framebuffer[8], 0x7FFF);
+    }
+
+    #[test]
+    fn should_convert_both_models_to_rgb() {
+        assert_eq!(to_rgb(0, false), [0xFF; 3]);
+        assert_eq!(to_rgb(2, false), [0x55; 3]);
+        assert_eq!(to_rgb(0x7FFF, true), [0xFF; 3]);
+        assert_eq!(to_rgb(0x001F, true), [0xFF, 0x00, 0x00]);
+        assert_eq!(to_rgb(0x0200, true), [0x00, 0x84, 0x00]);
+    }
+}
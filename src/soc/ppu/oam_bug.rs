@@ -0,0 +1,129 @@
+use std::ops::RangeInclusive;
+use super::*;
+
+// https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+// On DMG, putting an address of 0xFE00-0xFEFF on the bus while the PPU scans OAM mangles
+// the row of two entries it is reading, one row per M-cycle. OAM is seen as 16 bit words
+const OAM_AREA: RangeInclusive<Address> = 0xFE00..=0xFEFF;
+const ROW_WORDS: usize = 4;
+const ROWS: usize = OAM_SIZE / (ROW_WORDS * 2);
+const ROW_CYCLES: u32 = OAM_SCAN_CYCLES / ROWS as u32;
+
+impl Ppu {
+    pub fn corrupt_oam(&mut self, address: Address, corruption: OamCorruption) {
+        if self.cgb || !self.enabled() || self.visible_mode() != Mode::OamScan || !OAM_AREA.contains(&address) {
+            return;
+        }
+        // The first row is never hit
+        let row = (self.line_cycles / ROW_CYCLES) as usize;
+        if row == 0 {
+            return;
+        }
+        trace!("OAM bug ({:?}) on row {} of line {}", corruption, row, self.ly);
+
+        match corruption {
+            OamCorruption::Write => {
+                let (a, b, c) = (self.word(row, 0), self.word(row - 1, 0), self.word(row - 1, 2));
+                self.set_word(row, 0, ((a ^ c) & (b ^ c)) ^ c);
+                self.copy_words(row - 1, row, 1);
+            }
+            OamCorruption::Read => self.read_corruption(row),
+            OamCorruption::ReadIncrease => {
+                // The rows before take part too, except near both ends of OAM
+                if (4..ROWS - 1).contains(&row) {
+                    let (a, b) = (self.word(row - 2, 0), self.word(row - 1, 0));
+                    let (c, d) = (self.word(row, 0), self.word(row - 1, 2));
+                    self.set_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+                    self.copy_words(row - 1, row, 0);
+                    self.copy_words(row - 1, row - 2, 0);
+                }
+                self.read_corruption(row);
+            }
+        }
+    }
+
+    fn read_corruption(&mut self, row: usize) {
+        let (a, b, c) = (self.word(row, 0), self.word(row - 1, 0), self.word(row - 1, 2));
+        self.set_word(row, 0, b | (a & c));
+        self.copy_words(row - 1, row, 1);
+    }
+
+    fn word(&self, row: usize, word: usize) -> u16 {
+        let offset = (row * ROW_WORDS + word) * 2;
+        u16::from_le_bytes([self.oam[offset], self.oam[offset + 1]])
+    }
+
+    fn set_word(&mut self, row: usize, word: usize, value: u16) {
+        let offset = (row * ROW_WORDS + word) * 2;
+        self.oam[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // Copies the words of a row starting at the given one into another row
+    fn copy_words(&mut self, from: usize, to: usize, first: usize) {
+        for word in first..ROW_WORDS {
+            self.set_word(to, word, self.word(from, word));
+        }
+    }
+}
+
+#[cfg(test)]
+mod oam_bug_tests {
+    use super::*;
+
+    // Every OAM word set to its own index, scanning the given row of line 1
+    fn scanning(row: u32) -> Ppu {
+        let mut ppu = Ppu::new();
+        for word in 0..OAM_SIZE / 2 {
+            ppu.oam[word * 2..word * 2 + 2].copy_from_slice(&(word as u16).to_le_bytes());
+        }
+        ppu.write_register(LCDC, LCD_ENABLE);
+        ppu.tick(LINE_CYCLES + row * ROW_CYCLES);
+        ppu
+    }
+
+    #[test]
+    fn should_corrupt_row_on_write() {
+        let mut ppu = scanning(5);
+        ppu.corrupt_oam(0xFE10, OamCorruption::Write);
+
+        // ((20 ^ 18) & (16 ^ 18)) ^ 18
+        assert_eq!(ppu.word(5, 0), 16);
+        assert_eq!(ppu.word(5, 1), 17);
+        assert_eq!(ppu.word(5, 3), 19);
+        assert_eq!(ppu.word(6, 0), 24);
+    }
+
+    #[test]
+    fn should_corrupt_rows_before_on_read_increase() {
+        let mut ppu = scanning(5);
+        ppu.corrupt_oam(0xFEFF, OamCorruption::ReadIncrease);
+
+        // (16 & (12 | 20 | 18)) | (12 & 20 & 18), copied over rows 3 and 5, then the read
+        assert_eq!(ppu.word(4, 0), 16);
+        assert_eq!(ppu.word(3, 0), 16);
+        assert_eq!(ppu.word(3, 2), 18);
+        assert_eq!(ppu.word(5, 0), 16 | (16 & 18));
+        assert_eq!(ppu.word(2, 0), 8);
+    }
+
+    #[test]
+    fn should_leave_oam_alone_outside_the_scan() {
+        let mut ppu = scanning(5);
+        ppu.corrupt_oam(0xFDFF, OamCorruption::Write);
+        ppu.corrupt_oam(0xFF00, OamCorruption::Read);
+        assert_eq!(ppu.word(5, 0), 20);
+
+        let mut first_row = scanning(0);
+        first_row.corrupt_oam(0xFE00, OamCorruption::Write);
+        assert_eq!(first_row.word(0, 0), 0);
+
+        let mut drawing = scanning(OAM_SCAN_CYCLES / ROW_CYCLES);
+        drawing.corrupt_oam(0xFE00, OamCorruption::Write);
+        assert_eq!(drawing.word(19, 0), 76);
+
+        let mut cgb = scanning(5);
+        cgb.set_cgb(true);
+        cgb.corrupt_oam(0xFE00, OamCorruption::Write);
+        assert_eq!(cgb.word(5, 0), 20);
+    }
+}
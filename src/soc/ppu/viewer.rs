@@ -0,0 +1,226 @@
+use std::fmt::Write;
+use super::*;
+
+// Debug views of VRAM and OAM as they are right now, none of them goes through the CPU
+// access restrictions
+const TILES_PER_BANK: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const MAP_SIZE: usize = 256;
+const SWATCH_SIZE: usize = 16;
+const OAM_COLUMNS: usize = 10;
+const VIEWPORT_OUTLINE: [u8; 3] = [0xFF, 0x00, 0x00];
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // RGBA in row order, transparent where there is nothing to show
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image { width, height, rgba: vec![0; width * height * 4] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, [r, g, b]: [u8; 3]) {
+        let offset = (y * self.width + x) * 4;
+        self.rgba[offset..offset + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+impl Ppu {
+    // Tiles in VRAM order, 16 a row, in gray shades of their raw color index. CGB bank 1
+    // sits to the right of bank 0
+    pub fn tile_view(&self) -> Image {
+        let banks = if self.cgb { 2 } else { 1 };
+        let bank_width = TILES_PER_ROW * 8;
+        let mut image = Image::new(banks * bank_width, TILES_PER_BANK / TILES_PER_ROW * 8);
+
+        for bank in 0..banks {
+            for tile in 0..TILES_PER_BANK {
+                let address = bank * VRAM_SIZE + tile * 16;
+                let (left, top) = (bank * bank_width + tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let color = self.tile_pixel(address, x as u8, y as u8);
+                        image.set(left + x, top + y, to_rgb(color as u16, false));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // One of the two 32x32 maps with palettes and attributes applied, the part of the
+    // background on screen outlined when the map is the one in use
+    pub fn map_view(&self, second: bool) -> Image {
+        let map = if second { 0x1C00 } else { 0x1800 };
+        let mut image = Image::new(MAP_SIZE, MAP_SIZE);
+
+        for y in 0..MAP_SIZE {
+            for x in 0..MAP_SIZE {
+                let (color, attributes) = self.map_pixel(map, x as u8, y as u8);
+                let color = if self.cgb {
+                    palette_color(&self.background_palettes, attributes & CGB_PALETTE, color)
+                } else {
+                    shade(self.bgp, color) as u16
+                };
+                image.set(x, y, to_rgb(color, self.cgb));
+            }
+        }
+
+        if second == (self.lcdc & BG_MAP != 0) {
+            for x in 0..SCREEN_WIDTH {
+                let x = (self.scx as usize + x) % MAP_SIZE;
+                image.set(x, self.scy as usize, VIEWPORT_OUTLINE);
+                image.set(x, (self.scy as usize + SCREEN_HEIGHT - 1) % MAP_SIZE, VIEWPORT_OUTLINE);
+            }
+            for y in 0..SCREEN_HEIGHT {
+                let y = (self.scy as usize + y) % MAP_SIZE;
+                image.set(self.scx as usize, y, VIEWPORT_OUTLINE);
+                image.set((self.scx as usize + SCREEN_WIDTH - 1) % MAP_SIZE, y, VIEWPORT_OUTLINE);
+            }
+        }
+        image
+    }
+
+    // A row of four swatches per palette: BGP, OBP0 and OBP1 on DMG, the eight background
+    // palettes then the eight sprite ones on CGB
+    pub fn palette_view(&self) -> Image {
+        let palettes: Vec<[u16; 4]> = if self.cgb {
+            [&self.background_palettes, &self.sprite_palettes].iter()
+                .flat_map(|palettes| (0..8).map(move |palette| [0, 1, 2, 3].map(|color| palette_color(palettes, palette, color))))
+                .collect()
+        } else {
+            [self.bgp, self.obp0, self.obp1].iter()
+                .map(|&palette| [0, 1, 2, 3].map(|color| shade(palette, color) as u16))
+                .collect()
+        };
+
+        let mut image = Image::new(4 * SWATCH_SIZE, palettes.len() * SWATCH_SIZE);
+        for (row, colors) in palettes.iter().enumerate() {
+            for (column, &color) in colors.iter().enumerate() {
+                for y in 0..SWATCH_SIZE {
+                    for x in 0..SWATCH_SIZE {
+                        image.set(column * SWATCH_SIZE + x, row * SWATCH_SIZE + y, to_rgb(color, self.cgb));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // The 40 entries in OAM order, 10 a row, drawn as the current sprite size with flips
+    // and palettes applied. Color 0 stays transparent
+    pub fn oam_view(&self) -> Image {
+        let height = self.sprite_height() as usize;
+        let (cell_width, cell_height) = (8 + 1, height + 1);
+        let mut image = Image::new(OAM_COLUMNS * cell_width, OAM_ENTRIES / OAM_COLUMNS * cell_height);
+
+        for index in 0..OAM_ENTRIES {
+            let sprite = self.oam_entry(index);
+            let (left, top) = (index % OAM_COLUMNS * cell_width, index / OAM_COLUMNS * cell_height);
+            for y in 0..height {
+                for x in 0..8 {
+                    let color = self.sprite_pixel(&sprite, x as i16, y as i16, height as i16);
+                    if color == 0 {
+                        continue;
+                    }
+                    let color = if self.cgb {
+                        palette_color(&self.sprite_palettes, sprite.attributes & CGB_PALETTE, color)
+                    } else {
+                        let palette = if sprite.attributes & SECOND_PALETTE != 0 { self.obp1 } else { self.obp0 };
+                        shade(palette, color) as u16
+                    };
+                    image.set(left + x, top + y, to_rgb(color, self.cgb));
+                }
+            }
+        }
+        image
+    }
+
+    // One line per OAM entry with its screen position and decoded attributes
+    pub fn oam_listing(&self) -> String {
+        let mut listing = String::new();
+        for index in 0..OAM_ENTRIES {
+            let sprite = self.oam_entry(index);
+            let palette = if self.cgb {
+                format!("palette {} bank {}", sprite.attributes & CGB_PALETTE, (sprite.attributes & VRAM_BANK) >> 3)
+            } else {
+                format!("palette OBP{}", (sprite.attributes & SECOND_PALETTE) >> 4)
+            };
+            let flags: Vec<&str> = [(FLIP_X, "flip-x"), (FLIP_Y, "flip-y"), (BEHIND_BG, "behind-bg")].iter()
+                .filter(|(bit, _)| sprite.attributes & bit != 0)
+                .map(|&(_, name)| name)
+                .collect();
+            let line = format!("{:2} x {:4} y {:4} tile {:#04X} attributes {:#04X} {} {}",
+                               index, sprite.x, sprite.y, sprite.tile, sprite.attributes, palette, flags.join(" "));
+            writeln!(listing, "{}", line.trim_end()).unwrap();
+        }
+        listing
+    }
+
+    fn oam_entry(&self, index: usize) -> Sprite {
+        let entry = &self.oam[index * 4..index * 4 + 4];
+        Sprite { y: entry[0] as i16 - 16, x: entry[1] as i16 - 8, tile: entry[2], attributes: entry[3], index: index as u8, extra: false }
+    }
+}
+
+#[cfg(test)]
+mod viewer_tests {
+    use std::convert::TryInto;
+    use super::*;
+
+    fn pixel(image: &Image, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * image.width + x) * 4;
+        image.rgba[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn should_lay_out_tiles_of_both_banks() {
+        let mut ppu = Ppu::new();
+        ppu.vram[16 * 17] = 0x80;
+        ppu.vram[VRAM_SIZE + 16 * 17 + 1] = 0x80;
+        assert_eq!((ppu.tile_view().width, ppu.tile_view().height), (128, 192));
+
+        ppu.set_cgb(true);
+        let view = ppu.tile_view();
+        assert_eq!((view.width, view.height), (256, 192));
+        assert_eq!(pixel(&view, 8, 8), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(&view, 128 + 8, 8), [0x55, 0x55, 0x55, 0xFF]);
+        assert_eq!(pixel(&view, 9, 8), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn should_outline_wrapping_viewport() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(BGP, 0xE4);
+        ppu.write_register(SCX, 200);
+        ppu.write_register(SCY, 10);
+
+        let view = ppu.map_view(false);
+        assert_eq!(pixel(&view, 200, 10), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&view, 103, 100), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&view, 200, 153), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&view, 120, 100), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(&ppu.map_view(true), 200, 10), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn should_show_palettes_and_sprites() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(OBP1, 0x0C);
+        ppu.vram[16..32].copy_from_slice(&[0xFF; 16]);
+        ppu.oam[4..8].copy_from_slice(&[16, 8, 1, SECOND_PALETTE | FLIP_X]);
+
+        let view = ppu.palette_view();
+        assert_eq!((view.width, view.height), (64, 48));
+        assert_eq!(pixel(&view, 16, 32), [0x00, 0x00, 0x00, 0xFF]);
+
+        let view = ppu.oam_view();
+        assert_eq!((view.width, view.height), (90, 36));
+        assert_eq!(pixel(&view, 0, 0), [0x00; 4]);
+        assert_eq!(pixel(&view, 9, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(ppu.oam_listing().lines().nth(1).unwrap().ends_with("palette OBP1 flip-x"));
+    }
+}
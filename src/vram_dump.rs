@@ -0,0 +1,45 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use log::info;
+use crate::soc::ppu::{Image, Ppu};
+
+// Writes every debug view of the PPU into a directory, PNG images and a text listing of OAM
+pub fn dump(ppu: &Ppu, directory: &Path) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    save_image(&ppu.tile_view(), &directory.join("tiles.png"))?;
+    save_image(&ppu.map_view(false), &directory.join("map_9800.png"))?;
+    save_image(&ppu.map_view(true), &directory.join("map_9c00.png"))?;
+    save_image(&ppu.palette_view(), &directory.join("palettes.png"))?;
+    save_image(&ppu.oam_view(), &directory.join("oam.png"))?;
+    fs::write(directory.join("oam.txt"), ppu.oam_listing())?;
+    info!("VRAM dumped into {}", directory.display());
+    Ok(())
+}
+
+pub fn save_image(image: &Image, path: &Path) -> io::Result<()> {
+    let output = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(output, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image.rgba)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod vram_dump_tests {
+    use super::*;
+
+    #[test]
+    fn should_write_every_view() {
+        let directory = std::env::temp_dir().join(format!("rustboy-vram-{}", std::process::id()));
+        dump(&Ppu::new(), &directory).unwrap();
+
+        let decoder = png::Decoder::new(File::open(directory.join("tiles.png")).unwrap());
+        let (info, _) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (128, 192, png::ColorType::RGBA));
+        assert_eq!(fs::read_to_string(directory.join("oam.txt")).unwrap().lines().count(), 40);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}